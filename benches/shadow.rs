@@ -0,0 +1,71 @@
+//! Reconciliation of a single list with 10k rows.
+//!
+//! To compare against another revision of the shadow tree, save a baseline on that revision with
+//! `cargo bench --bench shadow -- --save-baseline before`, then run
+//! `cargo bench --bench shadow -- --baseline before` on this one.
+//! This benchmark only uses the public api and `id!`, so it can be copied to older revisions, like
+//! the flat `Vec<Child>` tree of before the arena. Those need `criterion` in their
+//! `[dev-dependencies]` and the `[[bench]]` entry of this crate's `Cargo.toml` as well.
+
+use bevy::prelude::*;
+use bevy_mod_reactive_ui::*;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const ROWS: u32 = 10_000;
+
+#[derive(Resource)]
+struct Rows(Vec<u32>);
+
+fn list(mut scene: ShadowScene, rows: Res<Rows>) {
+    scene.update(|shadow| {
+        shadow.spawn(id!(), NodeBundle::default).with(|shadow| {
+            for &row in rows.0.iter() {
+                shadow.spawn(id!(row), NodeBundle::default);
+            }
+        });
+    });
+}
+
+fn setup() -> (World, Schedule) {
+    let mut world = World::new();
+    world.insert_resource(Rows((0..ROWS).collect()));
+    let mut schedule = Schedule::default();
+    schedule.add_systems(list);
+    schedule.run(&mut world);
+    (world, schedule)
+}
+
+/// Alternate between two versions of the list.
+fn bench_toggle(c: &mut Criterion, name: &str, other: Vec<u32>) {
+    let (mut world, mut schedule) = setup();
+    let mut other = Rows(other);
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            std::mem::swap(&mut *world.resource_mut::<Rows>(), &mut other);
+            schedule.run(&mut world);
+        })
+    });
+}
+
+fn unchanged(c: &mut Criterion) {
+    bench_toggle(c, "unchanged", (0..ROWS).collect());
+}
+
+fn remove_middle(c: &mut Criterion) {
+    bench_toggle(
+        c,
+        "remove_middle",
+        (0..ROWS).filter(|&row| row != ROWS / 2).collect(),
+    );
+}
+
+fn move_first_to_last(c: &mut Criterion) {
+    bench_toggle(c, "move_first_to_last", (1..ROWS).chain([0]).collect());
+}
+
+fn reverse(c: &mut Criterion) {
+    bench_toggle(c, "reverse", (0..ROWS).rev().collect());
+}
+
+criterion_group!(benches, unchanged, remove_middle, move_first_to_last, reverse);
+criterion_main!(benches);
@@ -16,9 +16,9 @@ fn main() {
             }),
             ..default()
         }))
-        .add_plugins(ShadowScenePlugin)
+        .add_plugins(ShadowScenePlugin::default())
         .add_systems(Startup, setup)
-        .add_systems(Update, some_ui_system)
+        .add_systems(Update, some_ui_system.in_set(ShadowSystem::Reconcile))
         .insert_resource(Counter { value: 0 })
         .run();
 }
@@ -57,9 +57,13 @@ fn some_ui_system(mut shadow: ShadowScene, state: Res<Counter>, assets: Res<Load
                     .spawn(id!(), || button().on_click(on_up).on_event(on_up_key))
                     .spawn(id!(), || label("up", assets.text_style.clone()));
             }
-            shadow.spawn_dyn(id!(), state.is_changed(), || {
-                label(format!("count: {}", state.value), assets.text_style.clone())
-            });
+            shadow.text(
+                id!(),
+                [TextSection::new(
+                    format!("count: {}", state.value),
+                    assets.text_style.clone(),
+                )],
+            );
             if state.value > 0 {
                 shadow
                     .spawn(id!(), || button().on_click(on_down).on_event(on_down_key))
@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use bevy::{asset::ChangeWatcher, prelude::*};
+use bevy_mod_reactive_ui::*;
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins.set(AssetPlugin {
+            // edit assets/counter.ui.ron while the example is running to see it change.
+            watch_for_changes: ChangeWatcher::with_delay(Duration::from_millis(200)),
+            ..default()
+        }))
+        .add_plugins((ShadowScenePlugin::default(), ShadowTemplatePlugin))
+        .register_type::<Counter>()
+        .init_resource::<Counter>()
+        .add_template_handler("up", on_up)
+        .add_template_handler("down", on_down)
+        .add_systems(Startup, setup)
+        .run();
+}
+
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+struct Counter {
+    value: i32,
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn(Camera2dBundle::default());
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                ..default()
+            },
+            ..default()
+        },
+        ShadowTemplate::new(asset_server.load("counter.ui.ron")),
+    ));
+}
+
+fn on_up(mut state: ResMut<Counter>) {
+    state.value += 1;
+}
+
+fn on_down(mut state: ResMut<Counter>) {
+    state.value -= 1;
+}
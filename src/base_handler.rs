@@ -12,8 +12,20 @@ pub(crate) trait Handler: Send + Sync + 'static {
     fn handle(&self, world: &mut World, input: Self::In) -> Self::Out;
 
     fn apply(&self, world: &mut World);
+
+    /// Handle `input` on behalf of `entity`, so the handler can find the context of it's node.
+    fn handle_for(&self, world: &mut World, entity: Entity, input: Self::In) -> Self::Out {
+        world.insert_resource(HandlerEntity(entity));
+        let out = self.handle(world, input);
+        world.remove_resource::<HandlerEntity>();
+        out
+    }
 }
 
+/// The entity on behalf of which a handler is running.
+#[derive(Resource)]
+pub(crate) struct HandlerEntity(pub(crate) Entity);
+
 #[derive(Deref, DerefMut)]
 pub(crate) struct HandlerParam<'s, In, Out>(
     pub(crate) &'s mut Vec<Arc<dyn Handler<In = In, Out = Out>>>,
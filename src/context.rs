@@ -0,0 +1,78 @@
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
+use std::{
+    any::{Any, TypeId},
+    marker::PhantomData,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::base_handler::HandlerEntity;
+
+/// Read a value provided with `Shadow::provide` from a handler.
+/// The value is looked up from the entity the handler is attached to, the nearest provider wins.
+#[derive(SystemParam)]
+pub struct ShadowContext<'w, 's, T: Send + Sync + 'static> {
+    entity: Option<Res<'w, HandlerEntity>>,
+    parents: Query<'w, 's, &'static Parent>,
+    scopes: Query<'w, 's, &'static ContextScope>,
+    marker: PhantomData<T>,
+}
+
+/// A value provided to a fragment, linked to the values provided by the enclosing fragments.
+pub(crate) struct Provided<'a> {
+    pub type_id: TypeId,
+    pub value: Arc<dyn Any + Send + Sync>,
+    pub parent: Option<&'a Provided<'a>>,
+}
+
+/// The values visible to the nodes spawned directly in a `provide` fragment.
+/// Handlers find their context through the nearest ancestor with this component.
+#[derive(Component, Clone, Default)]
+pub(crate) struct ContextScope(Arc<Mutex<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>>);
+
+impl<'w, 's, T: Send + Sync + 'static> ShadowContext<'w, 's, T> {
+    /// The value of type `T` provided to the node of the running handler.
+    /// Returns `None` outside of handlers, or if no value of type `T` was provided.
+    pub fn get(&self) -> Option<Arc<T>> {
+        let mut entity = self.entity.as_ref()?.0;
+        loop {
+            if let Ok(scope) = self.scopes.get(entity) {
+                let values = scope.0.lock().unwrap_or_else(PoisonError::into_inner);
+                if let Some(value) = values.get(&TypeId::of::<T>()) {
+                    return value.clone().downcast().ok();
+                }
+            }
+            entity = self.parents.get(entity).ok()?.get();
+        }
+    }
+}
+
+impl<'a> Provided<'a> {
+    /// Find the nearest value of type `T`.
+    pub fn get<T: 'static>(&'a self) -> Option<&'a T> {
+        let mut provided = Some(self);
+        while let Some(p) = provided {
+            if p.type_id == TypeId::of::<T>() {
+                return p.value.downcast_ref();
+            }
+            provided = p.parent;
+        }
+        None
+    }
+}
+
+impl ContextScope {
+    /// Replace the values of the scope with everything visible from `provided`.
+    pub fn set(&self, provided: &Provided) {
+        let mut values = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        values.clear();
+        let mut provided = Some(provided);
+        while let Some(p) = provided {
+            values.entry(p.type_id).or_insert_with(|| p.value.clone());
+            provided = p.parent;
+        }
+    }
+
+    pub fn ptr_eq(&self, other: &ContextScope) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
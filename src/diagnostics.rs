@@ -0,0 +1,183 @@
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, DiagnosticMeasurement, DiagnosticsStore},
+    ecs::system::Command,
+    prelude::*,
+    utils::{get_short_name, HashMap, Instant},
+};
+use std::{
+    hash::{Hash, Hasher},
+    sync::{Arc, OnceLock},
+};
+
+use crate::key::StableHasher;
+
+/// Work done by the reconciler, by scene and by handler filter. Every frame the counts are added
+/// as measurements to diagnostics in the `DiagnosticsStore`, named `shadow <scene> <counter>`.
+/// Add the `LogDiagnosticsPlugin` to have them logged.
+///
+/// Scenes are named after their marker type, or after their system for local scenes. All
+/// `ShadowMount`s together are named `ShadowMount`. The counters of a scene are:
+/// - `visited`: nodes that were spawned or kept.
+/// - `spawned`: nodes that were inserted.
+/// - `removed`: nodes that were removed, including their descendants.
+/// - `reordered`: nodes of which the children changed.
+/// - `ordered`: `InsertChildrenInOrder` commands issued.
+/// - `reinserted`: bundles inserted again by `spawn_dyn`.
+///
+/// Handlers are counted by filter or event type, as `shadow <filter> handlers`.
+#[derive(Resource, Default)]
+pub struct ShadowDiagnostics {
+    scenes: HashMap<Arc<str>, (ReconcileStats, [Counter; 6])>,
+    /// Handlers by the type name of their filter.
+    handlers: HashMap<&'static str, (u32, Counter)>,
+}
+
+/// A diagnostic that is added to the `DiagnosticsStore` once it's first measured.
+struct Counter {
+    id: DiagnosticId,
+    name: String,
+}
+
+/// Work done by the reconciler in one update of a tree.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ReconcileStats {
+    pub visited: u32,
+    pub spawned: u32,
+    pub removed: u32,
+    pub reordered: u32,
+    pub ordered: u32,
+    pub reinserted: u32,
+}
+
+/// Add the stats of an update of `scene` to the counts of this frame.
+pub(crate) struct RecordStats {
+    pub scene: Arc<str>,
+    pub stats: ReconcileStats,
+}
+
+impl ShadowDiagnostics {
+    /// The id of the diagnostic for `counter` of `scene`.
+    pub fn id(scene: &str, counter: &str) -> DiagnosticId {
+        diagnostic_id(&format!("shadow {scene} {counter}"))
+    }
+
+    /// Count `handlers` that ran for `filter`.
+    pub(crate) fn record_handlers(world: &mut World, filter: &'static str, handlers: usize) {
+        if let Some(mut diagnostics) = world.get_resource_mut::<ShadowDiagnostics>() {
+            let (count, _) = diagnostics.handlers.entry(filter).or_insert_with(|| {
+                let name = format!("shadow {} handlers", get_short_name(filter));
+                (0, Counter::new(name))
+            });
+            *count += handlers as u32;
+        }
+    }
+}
+
+impl Counter {
+    fn new(name: String) -> Self {
+        Self {
+            id: diagnostic_id(&name),
+            name,
+        }
+    }
+
+    fn measure(&self, store: &mut DiagnosticsStore, value: u32, time: Instant) {
+        if store.get(self.id).is_none() {
+            store.add(Diagnostic::new(self.id, self.name.clone(), 20));
+        }
+        let diagnostic = store.get_mut(self.id).unwrap();
+        if diagnostic.is_enabled {
+            diagnostic.add_measurement(DiagnosticMeasurement {
+                time,
+                value: value as f64,
+            });
+        }
+    }
+}
+
+impl ReconcileStats {
+    const COUNTERS: [&'static str; 6] = [
+        "visited",
+        "spawned",
+        "removed",
+        "reordered",
+        "ordered",
+        "reinserted",
+    ];
+
+    /// The counts, in the order of `COUNTERS`.
+    fn values(&self) -> [u32; 6] {
+        [
+            self.visited,
+            self.spawned,
+            self.removed,
+            self.reordered,
+            self.ordered,
+            self.reinserted,
+        ]
+    }
+
+    fn add(&mut self, other: &ReconcileStats) {
+        self.visited += other.visited;
+        self.spawned += other.spawned;
+        self.removed += other.removed;
+        self.reordered += other.reordered;
+        self.ordered += other.ordered;
+        self.reinserted += other.reinserted;
+    }
+}
+
+impl RecordStats {
+    /// Record the stats of a `ShadowMount`, all mounts are counted as one scene.
+    pub fn mount(stats: ReconcileStats) -> Self {
+        static MOUNT: OnceLock<Arc<str>> = OnceLock::new();
+        Self {
+            scene: MOUNT.get_or_init(|| "ShadowMount".into()).clone(),
+            stats,
+        }
+    }
+}
+
+impl Command for RecordStats {
+    fn apply(self, world: &mut World) {
+        if let Some(mut diagnostics) = world.get_resource_mut::<ShadowDiagnostics>() {
+            let (stats, _) = diagnostics
+                .scenes
+                .entry(self.scene)
+                .or_insert_with_key(|scene| {
+                    let counters = ReconcileStats::COUNTERS
+                        .map(|counter| Counter::new(format!("shadow {scene} {counter}")));
+                    (ReconcileStats::default(), counters)
+                });
+            stats.add(&self.stats);
+        }
+    }
+}
+
+/// Add the counts of this frame to the `DiagnosticsStore`, and start counting from zero again.
+/// Scenes that weren't updated in this frame are measured as zero.
+pub(crate) fn diagnostics_system(
+    mut diagnostics: ResMut<ShadowDiagnostics>,
+    mut store: ResMut<DiagnosticsStore>,
+) {
+    let time = Instant::now();
+    let ShadowDiagnostics { scenes, handlers } = &mut *diagnostics;
+
+    for (stats, counters) in scenes.values_mut() {
+        for (counter, value) in counters.iter().zip(stats.values()) {
+            counter.measure(&mut store, value, time);
+        }
+        *stats = ReconcileStats::default();
+    }
+    for (count, counter) in handlers.values_mut() {
+        counter.measure(&mut store, *count, time);
+        *count = 0;
+    }
+}
+
+fn diagnostic_id(name: &str) -> DiagnosticId {
+    let mut h = StableHasher::new();
+    name.hash(&mut h);
+    // the upper half sets these ids apart from other diagnostics.
+    DiagnosticId::from_u128(0x5ad0_3c1e_7a0b_4c55_u128 << 64 | h.finish() as u128)
+}
@@ -0,0 +1,223 @@
+use bevy::{
+    ecs::{
+        bundle::DynamicBundle,
+        component::{ComponentId, Components, StorageType},
+        storage::Storages,
+        system::Command,
+        world::EntityMut,
+    },
+    prelude::*,
+    ptr::OwningPtr,
+    reflect::ReflectFromPtr,
+};
+use std::{
+    any::{type_name, TypeId},
+    convert::Infallible,
+    marker::PhantomData,
+};
+
+use crate::shadow::NodeState;
+
+/// Insert only the components of `bundle` that differ from the previous bundle of the node.
+pub(crate) struct DiffInsert<B> {
+    pub entity: Entity,
+    pub bundle: B,
+    pub previous: NodeState<BundleSnapshot>,
+}
+
+/// The components of the bundle that was inserted last, in the order of the bundle.
+/// Components that aren't registered for reflection are `None`.
+#[derive(Default)]
+pub(crate) struct BundleSnapshot {
+    bundle: Option<TypeId>,
+    components: Vec<Option<Box<dyn Reflect>>>,
+}
+
+/// The type of a bundle, along with a way to remove single components of it from an entity.
+#[derive(Clone, Copy)]
+pub(crate) struct BundleType {
+    pub id: TypeId,
+    remove: fn(&mut EntityMut, usize),
+}
+
+/// Remove the components of the `previous` bundle of a node that it's new `bundle` doesn't have.
+/// Components the bundles share are left alone, removing and inserting `Node` in the same frame
+/// would break the layout.
+pub(crate) struct RemoveStale {
+    pub entity: Entity,
+    pub previous: BundleType,
+    pub bundle: TypeId,
+}
+
+/// The component at `INDEX` of the bundle `B`, as a bundle of it's own. Only used to remove that
+/// component by type, it can't be constructed.
+struct Nth<B, const INDEX: usize>(Infallible, PhantomData<fn() -> B>);
+
+impl<B: Bundle> Command for DiffInsert<B> {
+    fn apply(self, world: &mut World) {
+        let DiffInsert {
+            entity,
+            bundle,
+            previous,
+        } = self;
+        if world.get_entity(entity).is_none() {
+            return;
+        }
+        let Some(registry) = world.get_resource::<AppTypeRegistry>().cloned() else {
+            world.entity_mut(entity).insert(bundle);
+            return;
+        };
+        let registry = registry.read();
+        let mut previous = previous.lock();
+
+        // the first bundle is inserted as a whole, it's components are compared from then on.
+        let bundle_id = world.bundles().get_id(TypeId::of::<B>());
+        let same_bundle = previous.bundle == Some(TypeId::of::<B>());
+        let Some(bundle_id) = bundle_id.filter(|_| same_bundle) else {
+            world.entity_mut(entity).insert(bundle);
+            let bundle_id = world.bundles().get_id(TypeId::of::<B>()).unwrap();
+            let components = world.bundles().get(bundle_id).unwrap().components();
+            let entity = world.entity(entity);
+            previous.bundle = Some(TypeId::of::<B>());
+            previous.components = components
+                .iter()
+                .map(|&id| {
+                    let type_id = world.components().get_info(id)?.type_id()?;
+                    let from_ptr = registry.get_type_data::<ReflectFromPtr>(type_id)?;
+                    // SAFETY: `from_ptr` was made for the type of the component.
+                    let value = unsafe { from_ptr.as_reflect_ptr(entity.get_by_id(id)?) };
+                    Some(value.clone_value())
+                })
+                .collect();
+            return;
+        };
+
+        let components = world
+            .bundles()
+            .get(bundle_id)
+            .unwrap()
+            .components()
+            .to_vec();
+        let mut entity = world.entity_mut(entity);
+        let mut index = 0;
+        bundle.get_components(&mut |_, component| {
+            let id = components[index];
+            let previous = &mut previous.components[index];
+            index += 1;
+
+            let info = entity.world().components().get_info(id).unwrap();
+            let drop_component = info.drop();
+            let from_ptr = info
+                .type_id()
+                .and_then(|type_id| registry.get_type_data::<ReflectFromPtr>(type_id));
+            // SAFETY: `from_ptr` was made for the type of the component.
+            let value =
+                from_ptr.map(|from_ptr| unsafe { from_ptr.as_reflect_ptr(component.as_ref()) });
+
+            // components that can't be compared are always written.
+            let unchanged = entity.contains_id(id)
+                && match (&*previous, value) {
+                    (Some(previous), Some(value)) => {
+                        previous.reflect_partial_eq(value) == Some(true)
+                    }
+                    _ => false,
+                };
+
+            if unchanged {
+                if let Some(drop_component) = drop_component {
+                    // SAFETY: the component is owned by this closure and not used afterwards.
+                    unsafe { drop_component(component) };
+                }
+            } else {
+                *previous = value.map(Reflect::clone_value);
+                // SAFETY: `id` is the id of the component's type.
+                unsafe { entity.insert_by_id(id, component) };
+            }
+        });
+    }
+}
+
+impl BundleType {
+    pub fn of<B: Bundle>() -> Self {
+        Self {
+            id: TypeId::of::<B>(),
+            remove: remove_nth::<B>,
+        }
+    }
+}
+
+impl Command for RemoveStale {
+    fn apply(self, world: &mut World) {
+        let bundles = world.bundles();
+        let (Some(previous), Some(bundle)) = (
+            bundles.get_id(self.previous.id),
+            bundles.get_id(self.bundle),
+        ) else {
+            return;
+        };
+        let bundle = bundles.get(bundle).unwrap().components();
+        let stale: Vec<usize> = (bundles.get(previous).unwrap().components().iter())
+            .enumerate()
+            .filter(|(_, id)| !bundle.contains(id))
+            .map(|(index, _)| index)
+            .collect();
+
+        if let Some(mut entity) = world.get_entity_mut(self.entity) {
+            for index in stale {
+                (self.previous.remove)(&mut entity, index);
+            }
+        }
+    }
+}
+
+// SAFETY: `component_ids` passes a single component of `B`, with the storage `B` initialized for
+// it. `Nth` can't be constructed, so it's components are never read or written.
+unsafe impl<B: Bundle, const INDEX: usize> Bundle for Nth<B, INDEX> {
+    fn component_ids(
+        components: &mut Components,
+        storages: &mut Storages,
+        ids: &mut impl FnMut(ComponentId),
+    ) {
+        let mut index = 0;
+        B::component_ids(components, storages, &mut |id| {
+            if index == INDEX {
+                ids(id);
+            }
+            index += 1;
+        });
+    }
+
+    unsafe fn from_components<T, F>(_: &mut T, _: &mut F) -> Self
+    where
+        F: for<'a> FnMut(&'a mut T) -> OwningPtr<'a>,
+        Self: Sized,
+    {
+        unreachable!("`Nth` is only used to remove components")
+    }
+}
+
+impl<B, const INDEX: usize> DynamicBundle for Nth<B, INDEX> {
+    fn get_components(self, _: &mut impl FnMut(StorageType, OwningPtr<'_>)) {
+        match self.0 {}
+    }
+}
+
+macro_rules! remove_nth {
+    ($($index:literal)*) => {
+        /// Remove the component at `index` of the bundle `B` from `entity`.
+        fn remove_nth<B: Bundle>(entity: &mut EntityMut, index: usize) {
+            match index {
+                $($index => {
+                    entity.remove::<Nth<B, $index>>();
+                })*
+                _ => warn!(
+                    "component {index} of `{}` was left behind, only the first 32 components of \
+                    a bundle are removed when it's replaced",
+                    type_name::<B>()
+                ),
+            }
+        }
+    };
+}
+
+remove_nth!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31);
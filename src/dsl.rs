@@ -0,0 +1,234 @@
+/// Declarative syntax for fragments, expanding to calls on a `Shadow`.
+///
+/// ```
+/// # use bevy::{prelude::*, utils::Duration};
+/// # use bevy_mod_reactive_ui::*;
+/// # #[derive(Resource)]
+/// # struct Counter {
+/// #     value: i32,
+/// # }
+/// # struct Item {
+/// #     id: u32,
+/// #     name: String,
+/// # }
+/// # #[derive(Resource)]
+/// # struct Items(Vec<Item>);
+/// # fn vbox() -> NodeBundle { default() }
+/// # fn panel() -> NodeBundle { default() }
+/// # fn button() -> ButtonBundle { default() }
+/// # fn label(text: impl Into<String>) -> TextBundle { TextBundle::from_section(text, default()) }
+/// # fn on_up(mut counter: ResMut<Counter>) { counter.value += 1; }
+/// # fn on_down(mut counter: ResMut<Counter>) { counter.value -= 1; }
+/// # fn custom_fragment(shadow: &mut Shadow) { shadow.spawn(id!(), || label("custom")); }
+/// fn counter(mut scene: ShadowScene, state: Res<Counter>, items: Res<Items>) {
+///     let slide = SlideTransition {
+///         direction: Vec2::X,
+///         duration: Duration::from_millis(200),
+///     };
+///     scene.update(|shadow| {
+///         ui!(shadow,
+///             vbox {
+///                 button(on_click = on_up) { label("up") }
+///                 dyn(state.is_changed()) label(format!("count: {}", state.value))
+///                 if state.value > 0 {
+///                     button(on_click = on_down) { label("down") }
+///                 } else {
+///                     label("at zero")
+///                 }
+///                 for item in items.0.iter() key item.id {
+///                     label(item.name.clone())
+///                 }
+///                 panel() with_transition(slide) { label("sliding") }
+///                 { custom_fragment(shadow) }
+///             }
+///         );
+///     });
+/// }
+/// # let mut world = World::new();
+/// # world.insert_resource(Counter { value: 0 });
+/// # let item = |id: u32, name: &str| Item { id, name: name.into() };
+/// # world.insert_resource(Items(vec![item(1, "one"), item(2, "two")]));
+/// # let mut schedule = Schedule::default();
+/// # schedule.add_systems(counter);
+/// # let mut texts = |world: &mut World| {
+/// #     let mut texts = world
+/// #         .query::<(Entity, &Text)>()
+/// #         .iter(world)
+/// #         .map(|(entity, text)| (text.sections[0].value.clone(), entity))
+/// #         .collect::<Vec<_>>();
+/// #     texts.sort();
+/// #     texts
+/// # };
+/// # schedule.run(&mut world);
+/// # let before = texts(&mut world);
+/// # let names = before.iter().map(|(text, _)| text.as_str()).collect::<Vec<_>>();
+/// # assert_eq!(names, ["at zero", "count: 0", "custom", "one", "sliding", "two", "up"]);
+/// #
+/// # world.resource_mut::<Counter>().value = 1;
+/// # world.resource_mut::<Items>().0.reverse();
+/// # schedule.run(&mut world);
+/// # let after = texts(&mut world);
+/// # let names = after.iter().map(|(text, _)| text.as_str()).collect::<Vec<_>>();
+/// # assert_eq!(names, ["count: 1", "custom", "down", "one", "sliding", "two", "up"]);
+/// # // keyed rows and nodes outside of the changed branch keep their entities.
+/// # for kept in ["custom", "one", "sliding", "two", "up"] {
+/// #     let entity = |texts: &[(String, Entity)]| texts.iter().find(|(t, _)| t == kept).unwrap().1;
+/// #     assert_eq!(entity(&before), entity(&after), "{kept}");
+/// # }
+/// ```
+///
+/// - `name(args) { children }` spawns `name(args)` with `children`. Both the arguments and the
+///   children are optional. Arguments of the form `method = value` are applied to the bundle as
+///   `.method(value)`, which works with `on_click`, `on_event` and similar extension methods.
+/// - `dyn(update)` in front of a node updates it's bundle when `update` is true, see `spawn_dyn`.
+/// - `with_transition(t)` after a node's arguments uses the transition `t` for it's children.
+/// - `if` and `for` work like they do in Rust. Loop items are identified by the expression after
+///   `key` if it's given, otherwise by their position.
+/// - `{ ... }` runs Rust code, which can use the `Shadow` by the name passed to the macro.
+///
+/// Nodes are identified by their position, and conditional branches and loops are scoped,
+/// so nodes keep their identity when a branch or loop changes.
+#[macro_export]
+macro_rules! ui {
+    ($shadow:ident, $($body:tt)*) => {{
+        let key = $crate::id!();
+        let mut index = 0usize;
+        $crate::ui!(@items $shadow key index $($body)*);
+    }};
+
+    (@items $s:ident $k:ident $i:ident) => {};
+
+    // rust code
+    (@items $s:ident $k:ident $i:ident { $($code:tt)* } $($rest:tt)*) => {
+        $s.scope(&$i, |$s| { $($code)* });
+        $i += 1;
+        $crate::ui!(@items $s $k $i $($rest)*);
+    };
+
+    // conditionals
+    (@items $s:ident $k:ident $i:ident if $($rest:tt)*) => {
+        $crate::ui!(@if $s $k $i [] [] $($rest)*);
+    };
+    (@if $s:ident $k:ident $i:ident [$($branches:tt)*] [$($cond:tt)*]
+        { $($body:tt)* } else if $($rest:tt)*) => {
+        $crate::ui!(@if $s $k $i [$($branches)* [$($cond)*] { $($body)* }] [] $($rest)*);
+    };
+    (@if $s:ident $k:ident $i:ident [$($branches:tt)*] [$($cond:tt)*]
+        { $($body:tt)* } else { $($else:tt)* } $($rest:tt)*) => {
+        $crate::ui!(@branches $s $k $i [$($branches)* [$($cond)*] { $($body)* }] { $($else)* });
+        $crate::ui!(@items $s $k $i $($rest)*);
+    };
+    (@if $s:ident $k:ident $i:ident [$($branches:tt)*] [$($cond:tt)*]
+        { $($body:tt)* } $($rest:tt)*) => {
+        $crate::ui!(@branches $s $k $i [$($branches)* [$($cond)*] { $($body)* }] {});
+        $crate::ui!(@items $s $k $i $($rest)*);
+    };
+    (@if $s:ident $k:ident $i:ident [$($branches:tt)*] [$($cond:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::ui!(@if $s $k $i [$($branches)*] [$($cond)* $next] $($rest)*);
+    };
+    (@branches $s:ident $k:ident $i:ident [$([$($cond:tt)*] { $($body:tt)* })*]
+        { $($else:tt)* }) => {
+        $(if $($cond)* {
+            $s.scope(&($i, stringify!($($cond)*)), |$s| {
+                let mut index = 0usize;
+                $crate::ui!(@items $s $k index $($body)*);
+            });
+        } else)* {
+            $s.scope(&($i, "else"), |$s| {
+                let mut index = 0usize;
+                $crate::ui!(@items $s $k index $($else)*);
+            });
+        }
+        $i += 1;
+    };
+
+    // loops
+    (@items $s:ident $k:ident $i:ident for $pat:pat in $($rest:tt)*) => {
+        $crate::ui!(@for $s $k $i [$pat] [] $($rest)*);
+    };
+    (@for $s:ident $k:ident $i:ident [$pat:pat] [$($iter:tt)*] key $($rest:tt)*) => {
+        $crate::ui!(@key $s $k $i [$pat] [$($iter)*] [] $($rest)*);
+    };
+    (@for $s:ident $k:ident $i:ident [$pat:pat] [$($iter:tt)*] { $($body:tt)* } $($rest:tt)*) => {
+        $s.scope(&$i, |$s| {
+            for (position, $pat) in ::std::iter::IntoIterator::into_iter($($iter)*).enumerate() {
+                $s.scope(&position, |$s| {
+                    let mut index = 0usize;
+                    $crate::ui!(@items $s $k index $($body)*);
+                });
+            }
+        });
+        $i += 1;
+        $crate::ui!(@items $s $k $i $($rest)*);
+    };
+    (@for $s:ident $k:ident $i:ident [$pat:pat] [$($iter:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::ui!(@for $s $k $i [$pat] [$($iter)* $next] $($rest)*);
+    };
+    (@key $s:ident $k:ident $i:ident [$pat:pat] [$($iter:tt)*] [$($key:tt)*]
+        { $($body:tt)* } $($rest:tt)*) => {
+        $s.scope(&$i, |$s| {
+            for $pat in $($iter)* {
+                $s.scope(&($($key)*), |$s| {
+                    let mut index = 0usize;
+                    $crate::ui!(@items $s $k index $($body)*);
+                });
+            }
+        });
+        $i += 1;
+        $crate::ui!(@items $s $k $i $($rest)*);
+    };
+    (@key $s:ident $k:ident $i:ident [$pat:pat] [$($iter:tt)*] [$($key:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::ui!(@key $s $k $i [$pat] [$($iter)*] [$($key)* $next] $($rest)*);
+    };
+
+    // nodes
+    (@items $s:ident $k:ident $i:ident dyn ($update:expr) $ctor:ident $($rest:tt)*) => {
+        $crate::ui!(@node [$s $k $i [$update] $ctor] $($rest)*);
+    };
+    (@items $s:ident $k:ident $i:ident $ctor:ident $($rest:tt)*) => {
+        $crate::ui!(@node [$s $k $i [false] $ctor] $($rest)*);
+    };
+    (@node $head:tt ($($args:tt)*) $($rest:tt)*) => {
+        $crate::ui!(@args $head [] [] [$($args)*] $($rest)*);
+    };
+    (@node $head:tt $($rest:tt)*) => {
+        $crate::ui!(@spawn $head [] [] $($rest)*);
+    };
+    (@args $head:tt [$($pos:tt)*] [$($named:tt)*] [] $($rest:tt)*) => {
+        $crate::ui!(@spawn $head [$($pos)*] [$($named)*] $($rest)*);
+    };
+    (@args $head:tt [$($pos:tt)*] [$($named:tt)*]
+        [$name:ident = $value:expr $(, $($args:tt)*)?] $($rest:tt)*) => {
+        $crate::ui!(@args $head [$($pos)*] [$($named)* .$name($value)] [$($($args)*)?] $($rest)*);
+    };
+    (@args $head:tt [$($pos:tt)*] [$($named:tt)*]
+        [$value:expr $(, $($args:tt)*)?] $($rest:tt)*) => {
+        $crate::ui!(@args $head [$($pos)* $value,] [$($named)*] [$($($args)*)?] $($rest)*);
+    };
+    (@spawn [$s:ident $k:ident $i:ident [$update:expr] $ctor:ident] [$($pos:tt)*] [$($named:tt)*]
+        with_transition ($transition:expr) { $($children:tt)* } $($rest:tt)*) => {
+        $s.spawn_dyn($k.clone().with(&$i), $update, || $ctor($($pos)*)$($named)*)
+            .with_transition(&$transition, |$s| {
+                let mut index = 0usize;
+                $crate::ui!(@items $s $k index $($children)*);
+            });
+        $i += 1;
+        $crate::ui!(@items $s $k $i $($rest)*);
+    };
+    (@spawn [$s:ident $k:ident $i:ident [$update:expr] $ctor:ident] [$($pos:tt)*] [$($named:tt)*]
+        { $($children:tt)* } $($rest:tt)*) => {
+        $s.spawn_dyn($k.clone().with(&$i), $update, || $ctor($($pos)*)$($named)*)
+            .with(|$s| {
+                let mut index = 0usize;
+                $crate::ui!(@items $s $k index $($children)*);
+            });
+        $i += 1;
+        $crate::ui!(@items $s $k $i $($rest)*);
+    };
+    (@spawn [$s:ident $k:ident $i:ident [$update:expr] $ctor:ident] [$($pos:tt)*] [$($named:tt)*]
+        $($rest:tt)*) => {
+        $s.spawn_dyn($k.clone().with(&$i), $update, || $ctor($($pos)*)$($named)*);
+        $i += 1;
+        $crate::ui!(@items $s $k $i $($rest)*);
+    };
+}
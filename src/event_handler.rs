@@ -1,7 +1,13 @@
-use bevy::prelude::*;
-use std::sync::Arc;
+use bevy::{
+    ecs::component::ComponentId,
+    prelude::*,
+    utils::{get_short_name, HashSet},
+};
+use std::{any::type_name, sync::Arc};
 
 use crate::base_handler::{Handler, HandlerImpl, HandlerParam};
+use crate::diagnostics::ShadowDiagnostics;
+use crate::{HandlerSchedule, ShadowSystem};
 
 #[derive(Component)]
 pub struct EventHandler<E: 'static> {
@@ -37,15 +43,47 @@ where
     }
 }
 
+/// Extension methods for running the `EventHandler`s of custom events.
+pub trait ShadowEventAppExt {
+    /// Run the `EventHandler<E>`s of all entities for every `E` that is sent.
+    /// `KeyboardInput` and `GamepadEvent` are registered by the `ShadowScenePlugin`, other events
+    /// must be registered after adding it. Panics otherwise.
+    fn add_ui_event<E: Event + Clone>(&mut self) -> &mut Self;
+}
+
+/// The `EventHandler`s that have a system, by component id.
+#[derive(Resource, Default)]
+pub(crate) struct UiEvents {
+    registered: HashSet<ComponentId>,
+}
+
+impl ShadowEventAppExt for App {
+    fn add_ui_event<E: Event + Clone>(&mut self) -> &mut Self {
+        let id = self.world.init_component::<EventHandler<E>>();
+        let mut events = self.world.get_resource_or_insert_with(UiEvents::default);
+        if !events.registered.insert(id) {
+            return self;
+        }
+
+        let schedule = HandlerSchedule::of(self, "`add_ui_event`");
+        self.add_event::<E>().add_systems(
+            schedule,
+            make_event_handler_system::<E>().in_set(ShadowSystem::Handlers),
+        )
+    }
+}
+
+type Handlers<T> = Vec<(Entity, Arc<dyn Handler<In = T, Out = ()>>)>;
+
 pub(crate) fn make_event_handler_system<T: Event + Clone>() -> impl System<In = (), Out = ()> {
     gather::<T>.pipe(run::<T>)
 }
 
 fn gather<T: Event + Clone>(
     mut events: EventReader<T>,
-    mut handlers: Query<&mut EventHandler<T>>,
+    mut handlers: Query<(Entity, &mut EventHandler<T>)>,
     mut unapplied: HandlerParam<T, ()>,
-) -> (Vec<T>, Vec<Arc<dyn Handler<In = T, Out = ()>>>) {
+) -> (Vec<T>, Handlers<T>) {
     unapplied.clear();
 
     if events.is_empty() {
@@ -54,18 +92,53 @@ fn gather<T: Event + Clone>(
 
     let events = events.iter().cloned().collect();
 
-    unapplied.extend(handlers.iter_mut().map(|h| h.handler.clone()));
+    let handlers: Handlers<T> = handlers
+        .iter_mut()
+        .map(|(entity, h)| (entity, h.handler.clone()))
+        .collect();
+    unapplied.extend(handlers.iter().map(|(_, handler)| handler.clone()));
 
-    (events, unapplied.clone())
+    (events, handlers)
 }
 
-fn run<T: Event + Clone>(
-    In((events, mut handlers)): In<(Vec<T>, Vec<Arc<dyn Handler<In = T, Out = ()>>>)>,
-    world: &mut World,
-) {
-    for handler in &mut handlers {
+fn run<T: Event + Clone>(In((events, handlers)): In<(Vec<T>, Handlers<T>)>, world: &mut World) {
+    if !handlers.is_empty() && !events.is_empty() {
+        let count = handlers.len() * events.len();
+        ShadowDiagnostics::record_handlers(world, type_name::<T>(), count);
+    }
+    for (entity, handler) in &handlers {
         for event in &events {
-            handler.handle(world, event.clone());
+            handler.handle_for(world, *entity, event.clone());
+        }
+    }
+}
+
+/// Warn once about every `EventHandler` of an event that wasn't registered with `add_ui_event`,
+/// since it would never run.
+pub(crate) fn unregistered_events_system(
+    world: &World,
+    mut archetypes: Local<usize>,
+    mut checked: Local<HashSet<ComponentId>>,
+) {
+    let Some(events) = world.get_resource::<UiEvents>() else {
+        return;
+    };
+    let prefix = type_name::<EventHandler<()>>().trim_end_matches("()>");
+
+    // archetypes are never removed, and only new ones can have new components.
+    for archetype in world.archetypes().iter().skip(*archetypes) {
+        for id in archetype.components() {
+            if events.registered.contains(&id) || !checked.insert(id) {
+                continue;
+            }
+            let name = world.components().get_info(id).unwrap().name();
+            if let Some(event) = name.strip_prefix(prefix) {
+                let event = get_short_name(&event[..event.len() - 1]);
+                warn!(
+                    "`EventHandler<{event}>` never runs, register it with `app.add_ui_event::<{event}>()`"
+                );
+            }
         }
     }
+    *archetypes = world.archetypes().len();
 }
@@ -1,15 +1,34 @@
-use bevy::prelude::*;
-use std::{marker::PhantomData, sync::Arc};
+use bevy::{prelude::*, utils::HashSet};
+use std::{
+    any::{type_name, TypeId},
+    marker::PhantomData,
+    sync::Arc,
+};
 
 use crate::base_handler::{Handler, HandlerImpl, HandlerParam};
+use crate::diagnostics::ShadowDiagnostics;
+use crate::{HandlerSchedule, ShadowSystem};
 
 #[derive(Component)]
 pub struct InteractionHandler<Filter: InteractionFilter> {
     handler: Arc<dyn Handler<In = (), Out = ()>>,
-    previous: Interaction,
     marker: PhantomData<Filter>,
 }
 
+/// The last change of the `Interaction` of an entity, shared by all of it's handlers so every
+/// filter sees the same transition.
+#[derive(Component, Clone, Copy)]
+pub(crate) struct InteractionHistory {
+    previous: Interaction,
+    current: Interaction,
+}
+
+/// The filters that have a system.
+#[derive(Resource, Default)]
+pub(crate) struct InteractionFilters {
+    registered: HashSet<TypeId>,
+}
+
 pub struct OnClick;
 
 pub struct OnClickEnd;
@@ -42,6 +61,22 @@ pub trait SetInteractionHandler: Sized {
     where
         T: SystemParamFunction<U, In = (), Out = ()>,
         U: 'static;
+
+    /// Run `on_interaction` when the `Interaction` changes in a way that passes `F`,
+    /// for example `on_interaction::<MyFilter, _, _>(handler)`.
+    fn on_interaction<F, T, U>(self, on_interaction: T) -> (Self, InteractionHandler<F>)
+    where
+        F: InteractionFilter,
+        T: SystemParamFunction<U, In = (), Out = ()>,
+        U: 'static;
+}
+
+/// Extension methods for running the `InteractionHandler`s of custom filters.
+pub trait ShadowInteractionAppExt {
+    /// Run the `InteractionHandler<F>`s of all entities of which the `Interaction` changes in a
+    /// way that passes `F`. The built-in filters are registered by the `ShadowScenePlugin`,
+    /// other filters must be registered after adding it. Panics otherwise.
+    fn add_interaction_filter<F: InteractionFilter>(&mut self) -> &mut Self;
 }
 
 impl<B> SetInteractionHandler for B
@@ -79,6 +114,41 @@ where
     {
         (self, InteractionHandler::new(handler))
     }
+
+    fn on_interaction<F, T, U>(self, handler: T) -> (Self, InteractionHandler<F>)
+    where
+        F: InteractionFilter,
+        T: SystemParamFunction<U, In = (), Out = ()>,
+        U: 'static,
+    {
+        (self, InteractionHandler::new(handler))
+    }
+}
+
+impl ShadowInteractionAppExt for App {
+    fn add_interaction_filter<F: InteractionFilter>(&mut self) -> &mut Self {
+        let mut filters = self
+            .world
+            .get_resource_or_insert_with(InteractionFilters::default);
+        let first = filters.registered.is_empty();
+        if !filters.registered.insert(TypeId::of::<F>()) {
+            return self;
+        }
+
+        let schedule = HandlerSchedule::of(self, "`add_interaction_filter`");
+        if first {
+            self.add_systems(
+                schedule.clone(),
+                interaction_history_system.in_set(ShadowSystem::Handlers),
+            );
+        }
+        self.add_systems(
+            schedule,
+            make_interaction_handler_system::<F>()
+                .in_set(ShadowSystem::Handlers)
+                .after(interaction_history_system),
+        )
+    }
 }
 
 impl<Filter: InteractionFilter> InteractionHandler<Filter> {
@@ -89,7 +159,14 @@ impl<Filter: InteractionFilter> InteractionHandler<Filter> {
     {
         Self {
             handler: Arc::new(HandlerImpl::new(handler)),
-            previous: Interaction::None,
+            marker: PhantomData,
+        }
+    }
+
+    /// A handler sharing it's system state with other handlers.
+    pub(crate) fn from_handler(handler: Arc<dyn Handler<In = (), Out = ()>>) -> Self {
+        Self {
+            handler,
             marker: PhantomData,
         }
     }
@@ -119,34 +196,61 @@ impl InteractionFilter for OnHoverEnd {
     }
 }
 
-pub(crate) fn make_interaction_handler_system<Filter: InteractionFilter>(
-) -> impl System<In = (), Out = ()> {
-    gather::<Filter>.pipe(run)
+type Handlers = Vec<(Entity, Arc<dyn Handler<In = (), Out = ()>>)>;
+
+fn make_interaction_handler_system<Filter: InteractionFilter>() -> impl System<In = (), Out = ()> {
+    gather::<Filter>.pipe(run::<Filter>)
+}
+
+/// Track the `Interaction` of every entity, so all filters see the same transition. Entities
+/// start out with a transition from `Interaction::None` when their `Interaction` is added.
+fn interaction_history_system(
+    world: &mut World,
+    changed: &mut QueryState<
+        (Entity, &Interaction, Option<&mut InteractionHistory>),
+        Changed<Interaction>,
+    >,
+) {
+    let mut added = Vec::new();
+    for (entity, interaction, history) in changed.iter_mut(world) {
+        match history {
+            Some(mut history) => {
+                history.previous = history.current;
+                history.current = *interaction;
+            }
+            None => added.push((entity, *interaction)),
+        }
+    }
+    for (entity, current) in added {
+        world.entity_mut(entity).insert(InteractionHistory {
+            previous: Interaction::None,
+            current,
+        });
+    }
 }
 
 fn gather<Filter: InteractionFilter>(
-    mut handlers: Query<(&mut InteractionHandler<Filter>, &Interaction), Changed<Interaction>>,
+    handlers: Query<
+        (Entity, &InteractionHandler<Filter>, &InteractionHistory),
+        Changed<InteractionHistory>,
+    >,
     mut unapplied: HandlerParam<(), ()>,
-) -> Vec<Arc<dyn Handler<In = (), Out = ()>>> {
+) -> Handlers {
     unapplied.clear();
-    unapplied.extend(
-        handlers
-            .iter_mut()
-            .filter_map(|(mut handler, interaction)| {
-                if Filter::filter(&handler.previous, interaction) {
-                    handler.previous = interaction.clone();
-                    Some(handler.handler.clone())
-                } else {
-                    handler.previous = interaction.clone();
-                    None
-                }
-            }),
-    );
-    unapplied.clone()
-}
-
-fn run(In(handlers): In<Vec<Arc<dyn Handler<In = (), Out = ()>>>>, world: &mut World) {
-    for handler in handlers {
-        handler.handle(world, ());
+    let handlers: Handlers = handlers
+        .iter()
+        .filter(|(_, _, history)| Filter::filter(&history.previous, &history.current))
+        .map(|(entity, handler, _)| (entity, handler.handler.clone()))
+        .collect();
+    unapplied.extend(handlers.iter().map(|(_, handler)| handler.clone()));
+    handlers
+}
+
+fn run<Filter: InteractionFilter>(In(handlers): In<Handlers>, world: &mut World) {
+    if !handlers.is_empty() {
+        ShadowDiagnostics::record_handlers(world, type_name::<Filter>(), handlers.len());
+    }
+    for (entity, handler) in handlers {
+        handler.handle_for(world, entity, ());
     }
 }
@@ -0,0 +1,180 @@
+#[cfg(debug_assertions)]
+use std::borrow::Cow;
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+/// Identifies a node among it's siblings.
+///
+/// Keys are hashed with a specified hash function, so the same key results in the same uid across
+/// builds, compiler versions and platforms. This makes uids suitable for persisting state.
+/// In debug builds the key retains a name for diagnostics.
+#[derive(Clone)]
+pub struct Key {
+    uid: u64,
+    #[cfg(debug_assertions)]
+    name: Option<Cow<'static, str>>,
+}
+
+/// 64 bit FNV-1a, with integers written in little endian.
+pub(crate) struct StableHasher(u64);
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+impl Key {
+    /// Key for a static name, usable in constants.
+    pub const fn from_static(name: &'static str) -> Self {
+        Self {
+            uid: fnv(name.as_bytes()),
+            #[cfg(debug_assertions)]
+            name: Some(Cow::Borrowed(name)),
+        }
+    }
+
+    /// Key for any hashable value.
+    pub fn new<T: Hash + ?Sized>(value: &T) -> Self {
+        let mut h = StableHasher::new();
+        value.hash(&mut h);
+        Self {
+            uid: h.finish(),
+            #[cfg(debug_assertions)]
+            name: None,
+        }
+    }
+
+    /// Derive a key from this key and `value`, keeping the name.
+    pub fn with<T: Hash + ?Sized>(mut self, value: &T) -> Self {
+        let mut h = StableHasher::new();
+        h.write_u64(self.uid);
+        value.hash(&mut h);
+        self.uid = h.finish();
+        self
+    }
+
+    pub fn uid(&self) -> u64 {
+        self.uid
+    }
+
+    /// The name of the key. Always `None` in release builds.
+    pub fn name(&self) -> Option<&str> {
+        #[cfg(debug_assertions)]
+        return self.name.as_deref();
+        #[cfg(not(debug_assertions))]
+        return None;
+    }
+}
+
+impl From<&'static str> for Key {
+    fn from(name: &'static str) -> Self {
+        Key::from_static(name)
+    }
+}
+
+impl From<String> for Key {
+    fn from(name: String) -> Self {
+        Self {
+            uid: fnv(name.as_bytes()),
+            #[cfg(debug_assertions)]
+            name: Some(Cow::Owned(name)),
+        }
+    }
+}
+
+macro_rules! impl_from_int {
+    ($($int:ty),*) => {$(
+        impl From<$int> for Key {
+            fn from(value: $int) -> Self {
+                #[allow(unused_mut)]
+                let mut key = Key::new(&value);
+                #[cfg(debug_assertions)]
+                {
+                    key.name = Some(Cow::Owned(value.to_string()));
+                }
+                key
+            }
+        }
+    )*};
+}
+
+impl_from_int!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{:?} ({:x})", name, self.uid),
+            None => write!(f, "{:x}", self.uid),
+        }
+    }
+}
+
+const fn fnv(bytes: &[u8]) -> u64 {
+    let mut hash = FNV_OFFSET;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+        i += 1;
+    }
+    hash
+}
+
+impl StableHasher {
+    pub fn new() -> Self {
+        Self(FNV_OFFSET)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as i64 as u64);
+    }
+}
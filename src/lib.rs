@@ -1,39 +1,206 @@
 mod base_handler;
+mod context;
+mod diagnostics;
+mod diff;
+mod dsl;
 mod event_handler;
 mod interaction_handler;
+mod key;
+mod scene;
 mod shadow;
+mod template;
+mod text;
 mod transition;
+mod validate;
+mod virtual_list;
 
-pub use event_handler::{EventHandler, SetEventHandler};
-use interaction_handler::{
-    make_interaction_handler_system, OnClick, OnClickEnd, OnHover, OnHoverEnd,
+pub use context::ShadowContext;
+pub use diagnostics::ShadowDiagnostics;
+use event_handler::UiEvents;
+pub use event_handler::{EventHandler, SetEventHandler, ShadowEventAppExt};
+pub use interaction_handler::{
+    InteractionFilter, InteractionHandler, OnClick, OnClickEnd, OnHover, OnHoverEnd,
+    SetInteractionHandler, ShadowInteractionAppExt,
+};
+pub use key::Key;
+pub use scene::{ShadowMount, ShadowScene, WorldShadowScene};
+pub use shadow::{ForeignChildren, NodeRef, NodeState, Shadow};
+pub use template::{
+    ShadowTemplate, ShadowTemplateAppExt, ShadowTemplatePlugin, TemplateKind, TemplateNode,
+    TemplateRect, TemplateStyle, UiTemplate, UiTemplateLoader,
 };
-pub use interaction_handler::{InteractionHandler, SetInteractionHandler};
-pub use shadow::{Shadow, ShadowScene};
 pub use transition::*;
+pub use validate::{ShadowRecovery, ShadowValidation};
+pub use virtual_list::{RowHeight, VirtualList};
 
 use bevy::{
+    diagnostic::DiagnosticsStore,
+    ecs::schedule::{BoxedScheduleLabel, ScheduleLabel},
     input::{gamepad::GamepadEvent, keyboard::KeyboardInput},
     prelude::*,
     ui::UiSystem,
 };
+use std::any::TypeId;
+
+/// Adds the handler systems, transitions, diagnostics and validation of shadow scenes.
+///
+/// In it's handler schedule, `Update` by default, all handlers run in `ShadowSystem::Handlers`
+/// before anything in `ShadowSystem::Reconcile`. Put the systems that update your scenes in
+/// `ShadowSystem::Reconcile` to see the effects of handlers in the same frame.
+/// In it's transition schedule, `PostUpdate` by default, `ShadowSystem::Transitions` runs after
+/// `UiSystem::Layout`.
+///
+/// Add it with `ShadowScenePlugin::default()` for these defaults.
+pub struct ShadowScenePlugin {
+    handler_schedule: BoxedScheduleLabel,
+    transition_schedule: BoxedScheduleLabel,
+    interaction_handlers: bool,
+    event_handlers: bool,
+}
+
+/// System sets of the `ShadowScenePlugin`.
+#[derive(SystemSet, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShadowSystem {
+    /// Runs the interaction and event handlers.
+    Handlers,
+    /// Updates scenes. Runs after `Handlers`, and includes the rendering of `ShadowTemplate`s.
+    Reconcile,
+    /// Animates transitions and scrolls virtual lists, once layout is known.
+    Transitions,
+}
+
+/// The schedule the handlers run in.
+#[derive(Resource)]
+pub(crate) struct HandlerSchedule(pub BoxedScheduleLabel);
+
+impl HandlerSchedule {
+    /// The handler schedule of the `ShadowScenePlugin` in `app`, for the systems added by `what`.
+    /// Panics if the plugin wasn't added yet, rather than putting the systems in the wrong
+    /// schedule.
+    pub fn of(app: &App, what: &str) -> BoxedScheduleLabel {
+        match app.world.get_resource::<HandlerSchedule>() {
+            Some(HandlerSchedule(schedule)) => schedule.clone(),
+            None => panic!("{what} needs the `ShadowScenePlugin`, add it first"),
+        }
+    }
+}
+
+impl ShadowScenePlugin {
+    /// Run the handlers and order `ShadowSystem::Reconcile` after them in `schedule`.
+    pub fn with_handler_schedule(mut self, schedule: impl ScheduleLabel) -> Self {
+        self.handler_schedule = Box::new(schedule);
+        self
+    }
+
+    /// Run `ShadowSystem::Transitions` in `schedule`, after `UiSystem::Layout` if it's there.
+    pub fn with_transition_schedule(mut self, schedule: impl ScheduleLabel) -> Self {
+        self.transition_schedule = Box::new(schedule);
+        self
+    }
+
+    /// Whether to run the handlers for `OnClick`, `OnClickEnd`, `OnHover` and `OnHoverEnd`.
+    pub fn with_interaction_handlers(mut self, enabled: bool) -> Self {
+        self.interaction_handlers = enabled;
+        self
+    }
+
+    /// Whether to run the handlers for `KeyboardInput` and `GamepadEvent`.
+    pub fn with_event_handlers(mut self, enabled: bool) -> Self {
+        self.event_handlers = enabled;
+        self
+    }
+}
 
-pub struct ShadowScenePlugin;
+impl Default for ShadowScenePlugin {
+    fn default() -> Self {
+        Self {
+            handler_schedule: Box::new(Update),
+            transition_schedule: Box::new(PostUpdate),
+            interaction_handlers: true,
+            event_handlers: true,
+        }
+    }
+}
 
 impl Plugin for ShadowScenePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            (
-                make_interaction_handler_system::<OnClick>(),
-                make_interaction_handler_system::<OnClickEnd>(),
-                make_interaction_handler_system::<OnHover>(),
-                make_interaction_handler_system::<OnHoverEnd>(),
-                event_handler::make_event_handler_system::<KeyboardInput>(),
-                event_handler::make_event_handler_system::<GamepadEvent>(),
-            ),
+        let handlers = self.handler_schedule.clone();
+        app.configure_sets(
+            handlers.clone(),
+            (ShadowSystem::Handlers, ShadowSystem::Reconcile).chain(),
+        )
+        .insert_resource(HandlerSchedule(handlers));
+
+        if self.interaction_handlers {
+            app.add_interaction_filter::<OnClick>()
+                .add_interaction_filter::<OnClickEnd>()
+                .add_interaction_filter::<OnHover>()
+                .add_interaction_filter::<OnHoverEnd>();
+        }
+
+        app.init_resource::<UiEvents>();
+        if self.event_handlers {
+            app.add_ui_event::<KeyboardInput>()
+                .add_ui_event::<GamepadEvent>();
+        }
+        if cfg!(debug_assertions) {
+            app.add_systems(Last, event_handler::unregistered_events_system);
+        }
+
+        app.init_resource::<DiagnosticsStore>()
+            .init_resource::<ShadowDiagnostics>()
+            .add_systems(Last, diagnostics::diagnostics_system);
+
+        app.init_resource::<ShadowValidation>()
+            .add_systems(Last, validate::validation_system);
+
+        let transitions = self.transition_schedule.clone();
+        app.configure_set(
+            transitions.clone(),
+            ShadowSystem::Transitions.after(UiSystem::Layout),
+        )
+        .add_systems(
+            transitions,
+            (slide_transition_system, virtual_list::virtual_list_system)
+                .in_set(ShadowSystem::Transitions),
+        );
+    }
+}
+
+/// Extension methods for tying shared shadow scenes to a `States` value.
+pub trait ShadowSceneAppExt {
+    /// Remove all nodes of `ShadowScene<M>` when exiting `state`.
+    /// The next update after entering `state` again rebuilds the scene from scratch.
+    fn bind_shadow_scene<M: Send + Sync + 'static>(&mut self, state: impl States) -> &mut Self;
+
+    /// Remove all nodes of `ShadowScene<M>` using `transition` when exiting `state`,
+    /// so exit animations can still play.
+    /// The next update after entering `state` again rebuilds the scene from scratch.
+    fn bind_shadow_scene_with_transition<M: Send + Sync + 'static>(
+        &mut self,
+        state: impl States,
+        transition: impl Transition + Send + Sync + 'static,
+    ) -> &mut Self;
+}
+
+impl ShadowSceneAppExt for App {
+    fn bind_shadow_scene<M: Send + Sync + 'static>(&mut self, state: impl States) -> &mut Self {
+        self.bind_shadow_scene_with_transition::<M>(state, DefaultTransition)
+    }
+
+    fn bind_shadow_scene_with_transition<M: Send + Sync + 'static>(
+        &mut self,
+        state: impl States,
+        transition: impl Transition + Send + Sync + 'static,
+    ) -> &mut Self {
+        assert_ne!(
+            TypeId::of::<M>(),
+            TypeId::of::<()>(),
+            "only shared scenes can be bound to a state, use a marker type other than `()`"
         );
 
-        app.add_systems(PostUpdate, slide_transition_system.after(UiSystem::Layout));
+        self.add_systems(OnExit(state), move |mut scene: ShadowScene<M>| {
+            scene.clear_with_transition(&transition);
+        })
     }
 }
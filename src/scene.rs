@@ -0,0 +1,491 @@
+use bevy::{
+    ecs::{
+        component::Tick,
+        system::{Command, ExclusiveSystemParam, SystemMeta, SystemParam, SystemState},
+        world::unsafe_world_cell::UnsafeWorldCell,
+    },
+    prelude::*,
+    utils::{get_short_name, HashMap},
+};
+use std::{
+    any::{type_name, TypeId},
+    collections::BTreeMap,
+    marker::PhantomData,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, Weak},
+};
+
+use crate::diagnostics::RecordStats;
+use crate::shadow::{Container, InsertChildrenInOrder, Shadow};
+use crate::transition::{DefaultTransition, Transition};
+
+/// A tree of entities that is reconciled with the fragments passed to it's `update` functions.
+///
+/// By default a scene is local to the system using it. Scenes with a marker type other than `()`
+/// are shared: every `ShadowScene<M>` with the same `M` refers to the same scene, no matter which
+/// system it's used in.
+#[derive(SystemParam)]
+pub struct ShadowScene<'w, 's, M: Send + Sync + 'static = ()> {
+    root: SceneRoot<'s, M>,
+    commands: Commands<'w, 's>,
+    entities: Query<'w, 's, ()>,
+}
+
+/// A `ShadowScene` for exclusive systems. Instead of pushing commands, every update is applied to
+/// the world right away, so the nodes it spawns exist as soon as the update returns.
+///
+/// Scenes are shared between `ShadowScene<M>` and `WorldShadowScene<M>` with the same marker
+/// type `M`, other than `()`.
+///
+/// ```text
+/// fn ui(world: &mut World, mut scene: WorldShadowScene) {
+///     let count = world.resource::<Counter>().0;
+///     scene.update(world, |sh| { ... });
+/// }
+/// ```
+pub struct WorldShadowScene<'s, M: Send + Sync + 'static = ()> {
+    state: &'s mut WorldSceneState,
+    marker: PhantomData<M>,
+}
+
+/// Mounts a shadow tree under the entity it's attached to.
+/// The tree lives as long as the component does, so when the entity is despawned recursively,
+/// nothing of the tree is left behind.
+#[derive(Component, Default)]
+pub struct ShadowMount {
+    tree: Container,
+}
+
+/// Registry of the shared scenes, by marker type.
+#[derive(Resource, Default)]
+pub(crate) struct ShadowScenes {
+    named: HashMap<TypeId, SharedScene>,
+    /// All scenes, including local ones, with their names.
+    all: Vec<(Arc<str>, Weak<Mutex<Scene>>)>,
+}
+
+pub(crate) type SharedScene = Arc<Mutex<Scene>>;
+
+/// A scene is made up of layers. Each layer is reconciled on it's own, and the root nodes of all
+/// layers are ordered by layer.
+#[derive(Default)]
+pub(crate) struct Scene {
+    layers: BTreeMap<i32, Container>,
+}
+
+#[doc(hidden)]
+pub struct SceneRoot<'s, M> {
+    shared: &'s SharedScene,
+    name: &'s Arc<str>,
+    marker: PhantomData<M>,
+}
+
+#[doc(hidden)]
+pub struct SceneState {
+    scene: SharedScene,
+    /// The name of the scene in diagnostics.
+    name: Arc<str>,
+}
+
+#[doc(hidden)]
+pub struct WorldSceneState {
+    scene: SceneState,
+    system: SystemState<(Commands<'static, 'static>, Query<'static, 'static, ()>)>,
+}
+
+struct InsertSceneInOrder {
+    parent: Entity,
+    scene: SharedScene,
+}
+
+impl<'w, 's, M: Send + Sync + 'static> ShadowScene<'w, 's, M> {
+    pub fn update<F>(&mut self, fragment: F)
+    where
+        F: FnOnce(&mut Shadow),
+    {
+        self.update_layer(0, None, &DefaultTransition, fragment);
+    }
+
+    pub fn update_with_transition<F>(&mut self, transition: &dyn Transition, fragment: F)
+    where
+        F: FnOnce(&mut Shadow),
+    {
+        self.update_layer(0, None, transition, fragment);
+    }
+
+    /// Update the scene as children of `parent`, in order.
+    /// If `parent` doesn't exist, the scene is despawned instead, see `despawn`.
+    pub fn update_in<F>(&mut self, parent: Entity, fragment: F)
+    where
+        F: FnOnce(&mut Shadow),
+    {
+        self.update_layer(0, Some(parent), &DefaultTransition, fragment);
+    }
+
+    /// Update the scene as children of `parent`, in order.
+    /// If `parent` doesn't exist, the scene is despawned instead, see `despawn`.
+    pub fn update_in_with_transition<F>(
+        &mut self,
+        parent: Entity,
+        transition: &dyn Transition,
+        fragment: F,
+    ) where
+        F: FnOnce(&mut Shadow),
+    {
+        self.update_layer(0, Some(parent), transition, fragment);
+    }
+
+    /// Update a single layer of the scene, optionally as children of `parent`.
+    ///
+    /// Layers are reconciled independently, so different systems can render into the same
+    /// shared scene by each updating their own layer. The root nodes of all layers are ordered
+    /// by layer, regardless of the order in which the layers are updated.
+    pub fn update_layer<F>(
+        &mut self,
+        layer: i32,
+        parent: Option<Entity>,
+        transition: &dyn Transition,
+        fragment: F,
+    ) where
+        F: FnOnce(&mut Shadow),
+    {
+        self.root.update_layer(
+            &mut self.commands,
+            &self.entities,
+            layer,
+            parent,
+            transition,
+            fragment,
+        );
+    }
+
+    /// Remove all nodes of the scene using the `DefaultTransition`.
+    pub fn clear(&mut self) {
+        self.clear_with_transition(&DefaultTransition);
+    }
+
+    /// Remove all nodes of the scene using `transition`, so exit animations can still play.
+    pub fn clear_with_transition(&mut self, transition: &dyn Transition) {
+        self.root
+            .clear(&mut self.commands, &self.entities, transition);
+    }
+
+    /// Despawn all entities of the scene immediately, without going through any transition.
+    /// The unmount effects of the nodes still run before they are despawned.
+    pub fn despawn(&mut self) {
+        self.root.despawn(&mut self.commands, &self.entities);
+    }
+}
+
+impl<'s, M: Send + Sync + 'static> WorldShadowScene<'s, M> {
+    pub fn update<F>(&mut self, world: &mut World, fragment: F)
+    where
+        F: FnOnce(&mut Shadow),
+    {
+        self.update_layer(world, 0, None, &DefaultTransition, fragment);
+    }
+
+    pub fn update_with_transition<F>(
+        &mut self,
+        world: &mut World,
+        transition: &dyn Transition,
+        fragment: F,
+    ) where
+        F: FnOnce(&mut Shadow),
+    {
+        self.update_layer(world, 0, None, transition, fragment);
+    }
+
+    /// Update the scene as children of `parent`, in order.
+    /// If `parent` doesn't exist, the scene is despawned instead, see `despawn`.
+    pub fn update_in<F>(&mut self, world: &mut World, parent: Entity, fragment: F)
+    where
+        F: FnOnce(&mut Shadow),
+    {
+        self.update_layer(world, 0, Some(parent), &DefaultTransition, fragment);
+    }
+
+    /// Update the scene as children of `parent`, in order.
+    /// If `parent` doesn't exist, the scene is despawned instead, see `despawn`.
+    pub fn update_in_with_transition<F>(
+        &mut self,
+        world: &mut World,
+        parent: Entity,
+        transition: &dyn Transition,
+        fragment: F,
+    ) where
+        F: FnOnce(&mut Shadow),
+    {
+        self.update_layer(world, 0, Some(parent), transition, fragment);
+    }
+
+    /// Update a single layer of the scene, optionally as children of `parent`.
+    /// See `ShadowScene::update_layer`.
+    pub fn update_layer<F>(
+        &mut self,
+        world: &mut World,
+        layer: i32,
+        parent: Option<Entity>,
+        transition: &dyn Transition,
+        fragment: F,
+    ) where
+        F: FnOnce(&mut Shadow),
+    {
+        self.apply(world, |root, commands, entities| {
+            root.update_layer(commands, entities, layer, parent, transition, fragment)
+        });
+    }
+
+    /// Remove all nodes of the scene using the `DefaultTransition`.
+    pub fn clear(&mut self, world: &mut World) {
+        self.clear_with_transition(world, &DefaultTransition);
+    }
+
+    /// Remove all nodes of the scene using `transition`, so exit animations can still play.
+    pub fn clear_with_transition(&mut self, world: &mut World, transition: &dyn Transition) {
+        self.apply(world, |root, commands, entities| {
+            root.clear(commands, entities, transition)
+        });
+    }
+
+    /// Despawn all entities of the scene immediately, without going through any transition.
+    /// The unmount effects of the nodes still run before they are despawned.
+    pub fn despawn(&mut self, world: &mut World) {
+        self.apply(world, |root, commands, entities| {
+            root.despawn(commands, entities)
+        });
+    }
+
+    /// Run `f` with the scene, then apply the commands it issued to `world`.
+    fn apply(
+        &mut self,
+        world: &mut World,
+        f: impl FnOnce(&mut SceneRoot<M>, &mut Commands, &Query<()>),
+    ) {
+        let WorldSceneState { scene, system } = &mut *self.state;
+        {
+            let (mut commands, entities) = system.get_mut(world);
+            f(&mut SceneRoot::new(scene), &mut commands, &entities);
+        }
+        // the scene is unlocked again, so the commands can order it's roots.
+        system.apply(world);
+    }
+}
+
+impl<'s, M> SceneRoot<'s, M> {
+    fn new(state: &'s SceneState) -> Self {
+        SceneRoot {
+            shared: &state.scene,
+            name: &state.name,
+            marker: PhantomData,
+        }
+    }
+
+    /// Lock the scene for a single call. Holding the lock for the whole run of a system would
+    /// deadlock systems with two params for the same scene.
+    fn lock(&self) -> MutexGuard<'s, Scene> {
+        self.shared.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn update_layer<F>(
+        &mut self,
+        commands: &mut Commands,
+        entities: &Query<()>,
+        layer: i32,
+        parent: Option<Entity>,
+        transition: &dyn Transition,
+        fragment: F,
+    ) where
+        F: FnOnce(&mut Shadow),
+    {
+        let mut scene = self.lock();
+        let tree = scene.layers.entry(layer).or_default();
+
+        // when the entity the layer was mounted in has been despawned, the layer is gone as well.
+        if let Some(mount) = tree.mount() {
+            if !entities.contains(mount) {
+                tree.forget(commands, entities);
+            }
+        }
+
+        match parent {
+            Some(parent) if !entities.contains(parent) => {
+                tree.forget(commands, entities);
+            }
+            Some(parent) => {
+                if tree.update(commands, Some(parent), transition, fragment) {
+                    commands.add(InsertSceneInOrder {
+                        parent,
+                        scene: self.shared.clone(),
+                    });
+                }
+            }
+            None => {
+                tree.update(commands, None, transition, fragment);
+            }
+        }
+        tree.run_effects(commands);
+        commands.add(RecordStats {
+            scene: self.name.clone(),
+            stats: tree.take_stats(),
+        });
+    }
+
+    fn clear(
+        &mut self,
+        commands: &mut Commands,
+        entities: &Query<()>,
+        transition: &dyn Transition,
+    ) {
+        let mut scene = self.lock();
+        for tree in scene.layers.values_mut() {
+            tree.clear(commands, entities, transition);
+        }
+        scene.layers.clear();
+    }
+
+    fn despawn(&mut self, commands: &mut Commands, entities: &Query<()>) {
+        let mut scene = self.lock();
+        for tree in scene.layers.values_mut() {
+            tree.forget(commands, entities);
+        }
+        scene.layers.clear();
+    }
+}
+
+impl ShadowMount {
+    /// Update the tree as children of `entity`, which should be the entity holding this component.
+    pub fn update<F>(&mut self, entity: Entity, commands: &mut Commands, fragment: F)
+    where
+        F: FnOnce(&mut Shadow),
+    {
+        self.update_with_transition(entity, commands, &DefaultTransition, fragment);
+    }
+
+    /// Update the tree as children of `entity`, which should be the entity holding this component.
+    pub fn update_with_transition<F>(
+        &mut self,
+        entity: Entity,
+        commands: &mut Commands,
+        transition: &dyn Transition,
+        fragment: F,
+    ) where
+        F: FnOnce(&mut Shadow),
+    {
+        if self
+            .tree
+            .update(commands, Some(entity), transition, fragment)
+        {
+            commands.add(InsertChildrenInOrder {
+                parent: entity,
+                children: self.tree.roots().collect(),
+            });
+        }
+        self.tree.run_effects(commands);
+        commands.add(RecordStats::mount(self.tree.take_stats()));
+    }
+
+    pub(crate) fn tree_mut(&mut self) -> &mut Container {
+        &mut self.tree
+    }
+}
+
+impl ShadowScenes {
+    /// Run `f` with every layer of every scene that still exists, along with the scene's name.
+    pub(crate) fn for_each_tree(&mut self, mut f: impl FnMut(&str, &mut Container)) {
+        self.all.retain(|(name, scene)| {
+            let Some(scene) = scene.upgrade() else {
+                return false;
+            };
+            let mut scene = scene.lock().unwrap_or_else(PoisonError::into_inner);
+            for tree in scene.layers.values_mut() {
+                f(name, tree);
+            }
+            true
+        });
+    }
+}
+
+impl Command for InsertSceneInOrder {
+    fn apply(self, world: &mut World) {
+        let children = {
+            let scene = self.scene.lock().unwrap_or_else(PoisonError::into_inner);
+            scene
+                .layers
+                .values()
+                .filter(|tree| tree.mount() == Some(self.parent))
+                .flat_map(|tree| tree.roots())
+                .collect()
+        };
+
+        InsertChildrenInOrder {
+            parent: self.parent,
+            children,
+        }
+        .apply(world);
+    }
+}
+
+unsafe impl<'s, M: Send + Sync + 'static> SystemParam for SceneRoot<'s, M> {
+    type State = SceneState;
+
+    type Item<'world, 'state> = SceneRoot<'state, M>;
+
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        SceneState::new::<M>(world, meta)
+    }
+
+    unsafe fn get_param<'world, 'state>(
+        state: &'state mut Self::State,
+        _: &SystemMeta,
+        _: UnsafeWorldCell<'world>,
+        _: Tick,
+    ) -> Self::Item<'world, 'state> {
+        SceneRoot::new(state)
+    }
+}
+
+impl<'a, M: Send + Sync + 'static> ExclusiveSystemParam for WorldShadowScene<'a, M> {
+    type State = WorldSceneState;
+
+    type Item<'s> = WorldShadowScene<'s, M>;
+
+    fn init(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        WorldSceneState {
+            scene: SceneState::new::<M>(world, meta),
+            system: SystemState::new(world),
+        }
+    }
+
+    fn get_param<'s>(state: &'s mut Self::State, _: &SystemMeta) -> Self::Item<'s> {
+        WorldShadowScene {
+            state,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl SceneState {
+    /// The state of the scene for `M`, registering a new scene if it's local or doesn't exist yet.
+    fn new<M: 'static>(world: &mut World, meta: &SystemMeta) -> Self {
+        let shared = TypeId::of::<M>() != TypeId::of::<()>();
+        let name: Arc<str> = if shared {
+            get_short_name(type_name::<M>()).into()
+        } else {
+            get_short_name(meta.name()).into()
+        };
+
+        let mut scenes = world.get_resource_or_insert_with(ShadowScenes::default);
+        if let Some(scene) = scenes.named.get(&TypeId::of::<M>()) {
+            return SceneState {
+                scene: scene.clone(),
+                name,
+            };
+        }
+        let scene = SharedScene::default();
+        if shared {
+            scenes.named.insert(TypeId::of::<M>(), scene.clone());
+        }
+        scenes.all.push((name.clone(), Arc::downgrade(&scene)));
+        SceneState { scene, name }
+    }
+}
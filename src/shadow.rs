@@ -1,75 +1,160 @@
-use bevy::ecs::system::{Command, SystemParam};
+use bevy::ecs::system::Command;
 use bevy::prelude::*;
-use smallvec::{smallvec, SmallVec};
+use bevy::ui::RelativeCursorPosition;
+use bevy::utils::{HashMap, HashSet};
+use smallvec::SmallVec;
+use std::any::{Any, TypeId};
+use std::hash::{Hash, Hasher};
+use std::panic::Location;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
+use crate::base_handler::{Handler, HandlerImpl};
+use crate::context::{ContextScope, Provided};
+use crate::diagnostics::ReconcileStats;
+use crate::diff::{BundleSnapshot, BundleType, DiffInsert, RemoveStale};
+use crate::key::{Key, StableHasher};
+use crate::text::{PatchText, TextSections};
 use crate::transition::{DefaultTransition, Transition};
-
-#[derive(SystemParam)]
-pub struct ShadowScene<'w, 's> {
-    root: Local<'s, Container>,
-    commands: Commands<'w, 's>,
-}
+use crate::validate::{Problem, ProblemKind, Removed, ValidationNodes};
+use crate::virtual_list::{ListState, VirtualList, VirtualListNode, VirtualListRows};
 
 pub struct Shadow<'a, 'w, 's> {
-    begin: usize,
-    cursor: usize,
-    len: usize,
-    count: usize,
-    changed: bool,
+    level: Option<Level>,
 
     parent: Option<Entity>,
     commands: &'a mut Commands<'w, 's>,
-    children: &'a mut Vec<Child>,
-    parent_cursor: &'a mut usize,
+    tree: &'a mut Container,
 
     transition: &'a dyn Transition,
     transition_root: bool,
+
+    context: Option<&'a Provided<'a>>,
+    /// Scope of the `provide` fragment that children are spawned in.
+    context_scope: Option<ContextScope>,
+    /// Key of the `scope` fragment that children are spawned in, mixed into their uids.
+    key: u64,
+}
+
+/// The children of a node while they are being reconciled.
+struct Level {
+    node: usize,
+    /// Children from the previous frame. Only taken from the node once they are needed,
+    /// so that leaf nodes stay cheap.
+    previous: Option<Vec<usize>>,
+    children: Vec<usize>,
+    /// Position in `previous` where the next child is expected.
+    cursor: usize,
+    /// Children must be inserted in the parent even if they didn't change.
+    reparent: bool,
+    status: Status,
+    /// Number of `provide` fragments visited so far.
+    scopes: usize,
+}
+
+/// Result of looking up a uid in the children of the previous frame.
+enum Lookup {
+    Found(usize),
+    /// The uid was already used by a sibling in this frame.
+    Visited(usize),
+    Missing,
+}
+
+/// What happened to a node in this frame.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Kept,
+    Inserted,
+    Updated,
 }
 
-struct Container(Vec<Child>);
+/// A one-shot system that runs with the entity of a node as input.
+type Effect = Arc<dyn Handler<In = Entity, Out = ()>>;
+
+/// Arena holding all nodes of a tree. Slot 0 is the root node.
+pub(crate) struct Container {
+    nodes: Vec<Child>,
+    free: Vec<usize>,
+    frame: u32,
+    mount: Option<Entity>,
+    /// The children of the root have changed, and must be inserted in the mount.
+    reorder: bool,
+    /// Mount and update effects that run once the tree has been inserted.
+    effects: Vec<(Entity, Effect)>,
+    /// The last uid derived for uids that were spawned more than once in this frame, by parent.
+    duplicates: HashMap<(usize, u64), u64>,
+    /// Work done since the stats were last taken.
+    stats: ReconcileStats,
+}
 
 struct Child {
     uid: u64,
     entity: Entity,
-    size: usize,
-    count: usize,
+    /// Where the node was spawned.
+    location: &'static Location<'static>,
+    /// The last frame in which this node was visited.
+    frame: u32,
+    /// Slots of the children of this node, in order.
+    children: Vec<usize>,
+    /// Slots of the children of this node, by uid.
+    index: HashMap<u64, usize>,
+    /// Memoized fragments of this node, by uid.
+    memos: HashMap<u64, Memo>,
+    /// Local state of this node, by type.
+    state: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    /// Effects that run when this node is removed.
+    unmount: Vec<Effect>,
+    /// Refs pointing to the entity of this node.
+    refs: Vec<NodeRef>,
+    /// Scopes of the `provide` fragments of this node, in order.
+    scopes: Vec<ContextScope>,
+    /// The scope this node was spawned in.
+    scope: Option<ContextScope>,
+    /// The type of the bundle this node was last inserted or updated with.
+    bundle: Option<BundleType>,
 }
 
-struct InsertChildrenInOrder {
-    parent: Entity,
-    children: SmallVec<[Entity; 8]>,
+/// The children a memoized fragment produced when it last ran.
+struct Memo {
+    hash: u64,
+    /// The last frame in which this memo was visited.
+    frame: u32,
+    slots: Vec<usize>,
+    /// Number of `provide` fragments the memo visited.
+    scopes: usize,
 }
 
-impl<'w, 's> ShadowScene<'w, 's> {
-    pub fn update<F>(&mut self, fragment: F)
-    where
-        F: FnOnce(&mut Shadow),
-    {
-        self.update_with_transition(&DefaultTransition, fragment);
-    }
+/// Handle to the local state of a node, see `Shadow::use_state`.
+/// The handle can be cloned into handlers to mutate the state from there.
+pub struct NodeState<T>(Arc<Mutex<T>>);
 
-    pub fn update_with_transition<F>(&mut self, transition: &dyn Transition, fragment: F)
-    where
-        F: FnOnce(&mut Shadow),
-    {
-        let mut cursor = 0;
-        let mut updater = Shadow {
-            begin: 1,
-            cursor: 1,
-            len: self.root.len(),
-            count: 0,
-            changed: false,
+/// Handle to the entity of a node, see `Shadow::node_ref`.
+/// It's empty until the node is inserted, and emptied again when the node is removed.
+#[derive(Clone, Default)]
+pub struct NodeRef(Arc<Mutex<Option<Entity>>>);
 
-            parent: None,
-            commands: &mut self.commands,
-            children: self.root.get(),
-            parent_cursor: &mut cursor,
+/// Where the children of an entity that weren't spawned by it's scene are kept when it's
+/// children are put in order. Insert it on the parent, for example along with the bundle of
+/// the node. Children that weren't spawned by the scene are never removed or reordered among
+/// themselves.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ForeignChildren {
+    /// Keep them after the child of the scene they were after.
+    #[default]
+    Preserve,
+    /// Keep them before all children of the scene.
+    Before,
+    /// Keep them after all children of the scene.
+    After,
+}
 
-            transition,
-            transition_root: true,
-        };
-        fragment(&mut updater);
-    }
+pub(crate) struct InsertChildrenInOrder {
+    pub parent: Entity,
+    pub children: SmallVec<[Entity; 8]>,
+}
+
+struct RunEffect {
+    effect: Effect,
+    entity: Entity,
 }
 
 impl<'a, 'w, 's> Shadow<'a, 'w, 's> {
@@ -83,233 +168,1178 @@ impl<'a, 'w, 's> Shadow<'a, 'w, 's> {
         fragment: F,
     ) {
         let mut updater = Shadow {
-            begin: self.begin,
-            cursor: self.cursor,
-            count: self.count,
-            len: self.len,
-            changed: false,
+            level: self.level.take(),
 
-            children: self.children,
+            parent: self.parent,
             commands: self.commands,
-            parent_cursor: self.parent_cursor,
+            tree: self.tree,
 
-            parent: self.parent.take(),
             transition,
             transition_root: self.transition_root,
+
+            context: self.context,
+            context_scope: self.context_scope.take(),
+            key: self.key,
         };
 
         fragment(&mut updater);
+    }
+
+    /// Provide `value` to `fragment` and all of it's descendants, including the handlers attached
+    /// to them. The value can be read with `context`, or with `ShadowContext` in handlers.
+    pub fn provide<T, F>(&mut self, value: T, fragment: F)
+    where
+        T: Send + Sync + 'static,
+        F: FnOnce(&mut Shadow),
+    {
+        let provided = Provided {
+            type_id: TypeId::of::<T>(),
+            value: Arc::new(value),
+            parent: self.context,
+        };
 
-        self.cursor = updater.cursor;
-        self.count = updater.count;
-        self.len = updater.len;
+        // the nodes spawned directly in the fragment share a scope, so handlers can find it.
+        let level = self.level.as_mut().unwrap();
+        let scopes = &mut self.tree.nodes[level.node].scopes;
+        if scopes.len() == level.scopes {
+            scopes.push(ContextScope::default());
+        }
+        let scope = scopes[level.scopes].clone();
+        scope.set(&provided);
+        level.scopes += 1;
+
+        let mut updater = Shadow {
+            level: self.level.take(),
+
+            parent: self.parent,
+            commands: self.commands,
+            tree: self.tree,
+
+            transition: self.transition,
+            transition_root: self.transition_root,
+
+            context: Some(&provided),
+            context_scope: Some(scope),
+            key: self.key,
+        };
+        fragment(&mut updater);
+        self.level = updater.level.take();
+    }
+
+    /// The value of type `T` provided by the nearest enclosing `provide`.
+    pub fn context<T: Send + Sync + 'static>(&self) -> Option<&'a T> {
+        self.context?.get()
     }
 
-    /// Spawn or update an entity. The uid must be unique.
+    /// Spawn or update an entity. The uid should be unique among it's siblings,
+    /// uids that are spawned more than once are told apart by the order in which they are spawned.
     /// If the entity already exists, it's bundle is not updated.
     /// The children of the node will be updated using the closure passed in `children`.
-    pub fn spawn<'b, F, B>(&'b mut self, uid: u64, bundle: F) -> Shadow<'b, 'w, 's>
+    #[track_caller]
+    pub fn spawn<'b, K, F, B>(&'b mut self, key: K, bundle: F) -> Shadow<'b, 'w, 's>
     where
+        K: Into<Key>,
         F: FnOnce() -> B,
         B: Bundle,
     {
-        if self.find_uid(uid) {
-            self.inner(self.children[self.cursor].entity, self.transition_root)
-        } else {
-            self.insert(uid, bundle())
+        let location = Location::caller();
+        match self.find(key.into(), location) {
+            Ok(slot) => self.inner(slot, self.transition_root, Status::Kept),
+            Err(uid) => self.insert(uid, bundle(), location),
         }
     }
 
-    /// Insert or update a node. The uid must be unique.
+    /// Insert or update a node. The uid should be unique among it's siblings, see `spawn`.
     /// If the entity already exists, it's bundle is only updated if `update` is true.
+    /// When the type of the bundle changes, the components of the previous bundle that the new one
+    /// doesn't have are removed. Components both bundles have are only overwritten.
     /// The children of the node will be updated using the closure passed in `children`.
-    pub fn spawn_dyn<'b, F, B>(
+    #[track_caller]
+    pub fn spawn_dyn<'b, K, F, B>(
         &'b mut self,
-        uid: u64,
+        key: K,
         update: bool,
         bundle: F,
     ) -> Shadow<'b, 'w, 's>
     where
+        K: Into<Key>,
         F: FnOnce() -> B,
         B: Bundle,
     {
-        if self.find_uid(uid) {
-            if update {
+        let location = Location::caller();
+        match self.find(key.into(), location) {
+            Ok(slot) if update => {
                 self.commands
-                    .entity(self.children[self.cursor].entity)
+                    .entity(self.tree.nodes[slot].entity)
                     .insert(bundle());
+                self.set_bundle::<B>(slot);
+                self.tree.stats.reinserted += 1;
+                self.inner(slot, self.transition_root, Status::Updated)
             }
-            self.inner(self.children[self.cursor].entity, self.transition_root)
+            Ok(slot) => self.inner(slot, self.transition_root, Status::Kept),
+            Err(uid) => self.insert(uid, bundle(), location),
+        }
+    }
+
+    /// Insert or update a node. The uid should be unique among it's siblings, see `spawn`.
+    /// The bundle is compared with the previous bundle of the node component by component, and
+    /// only the components that differ are written, so this can be called every frame without
+    /// triggering change detection on the components that stayed the same.
+    /// Components are compared through reflection, components that aren't registered for
+    /// reflection or can't be compared are written every time, like with `spawn_dyn`.
+    /// Components written by other systems, like `Node` by the layout, are left alone unless the
+    /// bundle changes them.
+    /// A previous bundle of another type is removed like with `spawn_dyn`.
+    #[track_caller]
+    pub fn spawn_diff<'b, K, F, B>(&'b mut self, key: K, bundle: F) -> Shadow<'b, 'w, 's>
+    where
+        K: Into<Key>,
+        F: FnOnce() -> B,
+        B: Bundle,
+    {
+        let location = Location::caller();
+        let mut shadow = match self.find(key.into(), location) {
+            Ok(slot) => self.inner(slot, self.transition_root, Status::Kept),
+            Err(uid) => self.insert(uid, (), location),
+        };
+        let node = shadow.level.as_ref().unwrap().node;
+        let previous = shadow.use_state(BundleSnapshot::default);
+        shadow.commands.add(DiffInsert {
+            entity: shadow.tree.nodes[node].entity,
+            bundle: bundle(),
+            previous,
+        });
+        shadow.set_bundle::<B>(node);
+        shadow
+    }
+
+    /// Insert or update a text node with `sections`.
+    /// Only the sections that changed since the last frame are written, the `Text` component
+    /// isn't touched at all if nothing changed. See `patch_text`.
+    #[track_caller]
+    pub fn text<'b, K, S>(&'b mut self, key: K, sections: S) -> Shadow<'b, 'w, 's>
+    where
+        K: Into<Key>,
+        S: IntoIterator<Item = TextSection>,
+    {
+        let sections: Vec<TextSection> = sections.into_iter().collect();
+        let location = Location::caller();
+        match self.find(key.into(), location) {
+            Ok(slot) => self
+                .inner(slot, self.transition_root, Status::Kept)
+                .patch_text(sections),
+            Err(uid) => {
+                let text = Text::from_sections(sections.clone());
+                let bundle = TextBundle { text, ..default() };
+                let mut shadow = self.insert(uid, bundle, location);
+                shadow
+                    .use_state(TextSections::default)
+                    .set(TextSections(sections));
+                shadow
+            }
+        }
+    }
+
+    /// Mix `key` into the uids of the nodes spawned directly in `fragment`,
+    /// so the same fragment can be used multiple times under one parent.
+    pub fn scope<K, F>(&mut self, key: K, fragment: F)
+    where
+        K: Hash,
+        F: FnOnce(&mut Shadow),
+    {
+        let mut h = StableHasher::new();
+        h.write_u64(self.key);
+        key.hash(&mut h);
+
+        let mut updater = Shadow {
+            level: self.level.take(),
+
+            parent: self.parent,
+            commands: self.commands,
+            tree: self.tree,
+
+            transition: self.transition,
+            transition_root: self.transition_root,
+
+            context: self.context,
+            context_scope: self.context_scope.clone(),
+            key: h.finish(),
+        };
+        fragment(&mut updater);
+        self.level = updater.level.take();
+    }
+
+    /// Run `fragment` for every item in a `scope` keyed by `key`.
+    /// The nodes of an item keep their identity when items are reordered.
+    pub fn keyed<I, K, KF, F>(&mut self, items: I, mut key: KF, mut fragment: F)
+    where
+        I: IntoIterator,
+        K: Hash,
+        KF: FnMut(&I::Item) -> K,
+        F: FnMut(&mut Shadow, I::Item),
+    {
+        for item in items {
+            self.scope(key(&item), |shadow| fragment(shadow, item));
+        }
+    }
+
+    /// Run `fragment` only if the hash of `deps` changed since the previous frame.
+    /// Otherwise the children it produced in the previous frame are kept as they are,
+    /// including their descendants. The key must be unique among the memos of this node.
+    pub fn memo<K, D, F>(&mut self, key: K, deps: D, fragment: F)
+    where
+        K: Into<Key>,
+        D: Hash,
+        F: FnOnce(&mut Shadow),
+    {
+        let uid = key.into().uid();
+        let mut h = StableHasher::new();
+        deps.hash(&mut h);
+        let hash = h.finish();
+
+        let level = self.level.as_mut().unwrap();
+        let frame = self.tree.frame;
+        level.open(self.tree);
+        let node = level.node;
+
+        let memo = self.tree.nodes[node].memos.remove(&uid);
+        let (slots, scopes) = match memo {
+            Some(memo)
+                if memo.hash == hash
+                    && memo.frame != frame
+                    && memo
+                        .slots
+                        .iter()
+                        .all(|&slot| self.tree.nodes[slot].frame != frame) =>
+            {
+                for &slot in memo.slots.iter() {
+                    self.tree.nodes[slot].frame = frame;
+                }
+                let previous = level.previous.as_deref().unwrap();
+                if previous[level.cursor.min(previous.len())..].starts_with(&memo.slots) {
+                    level.cursor += memo.slots.len();
+                }
+                level.children.extend_from_slice(&memo.slots);
+                // the slots keep their scopes, they may have been spawned in a `provide` of the
+                // fragment. later `provide`s of this node still get their own scopes.
+                level.scopes += memo.scopes;
+                (memo.slots, memo.scopes)
+            }
+            _ => {
+                let start = level.children.len();
+                let scopes = level.scopes;
+                fragment(self);
+                let level = self.level.as_ref().unwrap();
+                (level.children[start..].to_vec(), level.scopes - scopes)
+            }
+        };
+
+        self.tree.nodes[node].memos.insert(
+            uid,
+            Memo {
+                hash,
+                frame,
+                slots,
+                scopes,
+            },
+        );
+    }
+
+    /// Run `effect` with the entity of this node after it has been inserted.
+    /// Does nothing for the root of a scene.
+    pub fn on_mount<T, M>(mut self, effect: T) -> Self
+    where
+        T: SystemParamFunction<M, In = Entity, Out = ()>,
+        M: 'static,
+    {
+        if let Some(entity) = self.node_entity(Status::Inserted) {
+            self.tree
+                .effects
+                .push((entity, Arc::new(HandlerImpl::new(effect))));
+        }
+        self
+    }
+
+    /// Run `effect` with the entity of this node after it's bundle has been updated by `spawn_dyn`.
+    /// Does nothing for the root of a scene.
+    pub fn on_update<T, M>(mut self, effect: T) -> Self
+    where
+        T: SystemParamFunction<M, In = Entity, Out = ()>,
+        M: 'static,
+    {
+        if let Some(entity) = self.node_entity(Status::Updated) {
+            self.tree
+                .effects
+                .push((entity, Arc::new(HandlerImpl::new(effect))));
+        }
+        self
+    }
+
+    /// Run `effect` with the entity of this node when it's removed, before it's remove transition.
+    /// Like the bundle passed to `spawn`, the effect is only stored when the node is inserted.
+    /// Does nothing for the root of a scene.
+    pub fn on_unmount<T, M>(mut self, effect: T) -> Self
+    where
+        T: SystemParamFunction<M, In = Entity, Out = ()>,
+        M: 'static,
+    {
+        if self.node_entity(Status::Inserted).is_some() {
+            let node = self.level.as_ref().unwrap().node;
+            self.tree.nodes[node]
+                .unmount
+                .push(Arc::new(HandlerImpl::new(effect)));
+        }
+        self
+    }
+
+    /// The entity of this node, or `None` for the root of a scene.
+    pub fn entity(&self) -> Option<Entity> {
+        let node = self.level.as_ref()?.node;
+        (node != 0).then(|| self.tree.nodes[node].entity)
+    }
+
+    /// Point `node_ref` to the entity of this node for as long as the node exists.
+    /// Does nothing for the root of a scene.
+    pub fn node_ref(self, node_ref: &NodeRef) -> Self {
+        let Some(entity) = self.entity() else {
+            return self;
+        };
+        node_ref.set(Some(entity));
+
+        let node = self.level.as_ref().unwrap().node;
+        let refs = &mut self.tree.nodes[node].refs;
+        if !refs.iter().any(|r| Arc::ptr_eq(&r.0, &node_ref.0)) {
+            refs.push(node_ref.clone());
+        }
+        self
+    }
+
+    pub(crate) fn commands(&mut self) -> &mut Commands<'w, 's> {
+        self.commands
+    }
+
+    /// Update the sections of the `Text` of this node in place, adding a `Text` if there is none.
+    /// Only the values and styles of the sections that changed since the last call are written,
+    /// so the text isn't laid out again if nothing changed. Does nothing for the root of a scene.
+    pub fn patch_text<S>(mut self, sections: S) -> Self
+    where
+        S: IntoIterator<Item = TextSection>,
+    {
+        let Some(entity) = self.entity() else {
+            return self;
+        };
+        let sections: Vec<TextSection> = sections.into_iter().collect();
+        let previous = self.use_state(TextSections::default);
+        let mut previous = previous.lock();
+        if !previous.matches(&sections) {
+            previous.0.clone_from(&sections);
+            self.commands.add(PatchText { entity, sections });
+        }
+        self
+    }
+
+    /// Turn this node into a list of which only the rows in view are spawned, plus
+    /// `list.overscan` rows above and below. `row` is called with the index of each of those rows
+    /// and should spawn one node for it, keyed by index or by the key of the item so rows that
+    /// stay in view are kept while scrolling.
+    /// The list scrolls with the mouse wheel while hovered. The node should have a fixed height
+    /// and `Overflow::clip()`, the rows are spawned in a column inside it.
+    /// Does nothing for the root of a scene.
+    pub fn virtual_list<F>(mut self, list: VirtualList, mut row: F)
+    where
+        F: FnMut(&mut Shadow, usize),
+    {
+        let Some(entity) = self.entity() else {
+            return;
+        };
+        let node = self.level.as_ref().unwrap().node;
+        let mounted = self.tree.nodes[node]
+            .state
+            .contains_key(&TypeId::of::<ListState>());
+        let state = self.use_state(ListState::default);
+        if !mounted {
+            self.commands.entity(entity).insert((
+                VirtualListNode(state.clone()),
+                RelativeCursorPosition::default(),
+            ));
+        }
+
+        let window = state.lock().window(&list);
+        let style = Style {
+            flex_direction: FlexDirection::Column,
+            flex_shrink: 0.0,
+            width: Val::Percent(100.0),
+            min_height: Val::Px(window.height),
+            top: Val::Px(-window.offset),
+            padding: UiRect::top(Val::Px(window.top)),
+            ..default()
+        };
+        self.spawn_diff("rows", || {
+            (NodeBundle { style, ..default() }, VirtualListRows(state))
+        })
+        // rows that scroll out of view are gone at once, they would push the others around.
+        .with_transition(&DefaultTransition, |shadow| {
+            for index in window.rows {
+                row(shadow, index);
+            }
+        });
+    }
+
+    /// The entity of this node, if it's status in this frame is `status`.
+    fn node_entity(&mut self, status: Status) -> Option<Entity> {
+        let level = self.level.as_ref()?;
+        if level.node == 0 || level.status != status {
+            return None;
+        }
+        Some(self.tree.nodes[level.node].entity)
+    }
+
+    /// Get the local state of type `T` of this node, or initialize it using `init`.
+    /// The state persists as long as the node stays mounted. Each node has one state per type.
+    pub fn use_state<T, F>(&mut self, init: F) -> NodeState<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T,
+    {
+        let node = self.level.as_ref().unwrap().node;
+        self.tree.nodes[node]
+            .state
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(NodeState(Arc::new(Mutex::new(init())))))
+            .downcast_ref::<NodeState<T>>()
+            .unwrap()
+            .clone()
+    }
+
+    /// Find the child for `key`, or return the uid a new child must be inserted with.
+    fn find(&mut self, key: Key, location: &'static Location<'static>) -> Result<usize, u64> {
+        let base = if self.key == 0 {
+            key.uid()
         } else {
-            self.insert(uid, bundle())
+            mix(key.uid(), self.key)
+        };
+        let mut uid = base;
+        let mut sibling = None;
+        loop {
+            match self.find_uid(uid) {
+                Lookup::Found(slot) => return Ok(slot),
+                Lookup::Visited(slot) => {
+                    // the next occurrence of a uid is spawned under a uid derived from it.
+                    sibling.get_or_insert(slot);
+                    let node = self.level.as_ref().unwrap().node;
+                    let duplicates = &mut self.tree.duplicates;
+                    uid = match duplicates.get(&(node, base)) {
+                        Some(&last) if last != uid => last,
+                        _ => mix(uid, 1),
+                    };
+                    duplicates.insert((node, base), uid);
+                }
+                Lookup::Missing => {
+                    if let (true, Some(slot)) = (cfg!(debug_assertions), sibling) {
+                        warn!(
+                            "key {:?} spawned at {} is already used by a sibling spawned at {}, \
+                             use `scope` or `keyed` to give it a stable identity",
+                            key, location, self.tree.nodes[slot].location,
+                        );
+                    }
+                    return Err(uid);
+                }
+            }
         }
     }
 
-    /// Attempt to find a child with the queried uid. Returns `true` if it was found.
-    /// After calling this function, `self.cursor` is set to the new position for the queried uid.
-    fn find_uid(&mut self, uid: u64) -> bool {
-        let mut i = self.cursor;
-        for j in self.count..self.len {
-            if self.children[i].uid == uid {
-                // in-between children are considered to have disappeared
-                self.remove(j - self.count);
-                // uid has been found, and it's now at self.cursor.
-                return true;
-            } else {
-                i += self.children[i].size;
+    /// Attempt to find a child with the queried uid that was not visited yet in this frame.
+    /// If it was found, it's appended to the children of this node.
+    fn find_uid(&mut self, uid: u64) -> Lookup {
+        let level = self.level.as_mut().unwrap();
+        let frame = self.tree.frame;
+        level.open(self.tree);
+        let previous = level.previous.as_deref().unwrap();
+
+        // most of the time children are in the same order as in the previous frame,
+        // so try the expected position, or the one after in case a child has disappeared.
+        let expected = previous[level.cursor.min(previous.len())..]
+            .iter()
+            .take(2)
+            .position(|&slot| self.tree.nodes[slot].uid == uid);
+        let slot = if let Some(i) = expected {
+            level.cursor += i + 1;
+            previous[level.cursor - 1]
+        } else if let Some(&slot) = self.tree.nodes[level.node].index.get(&uid) {
+            slot
+        } else {
+            return Lookup::Missing;
+        };
+
+        let child = &mut self.tree.nodes[slot];
+        if child.frame == frame {
+            return Lookup::Visited(slot);
+        }
+        // a node of which the entity was despawned by something else is spawned again, the old
+        // node is removed with the children that weren't visited.
+        if self.commands.get_entity(child.entity).is_none() {
+            return Lookup::Missing;
+        }
+        child.frame = frame;
+        level.children.push(slot);
+        self.attach_scope(slot);
+        Lookup::Found(slot)
+    }
+
+    /// Attach the child in `slot` to the scope of the `provide` fragment it's spawned in, if any.
+    fn attach_scope(&mut self, slot: usize) {
+        let child = &mut self.tree.nodes[slot];
+        match (&self.context_scope, &child.scope) {
+            (None, None) => {}
+            (Some(scope), Some(current)) if scope.ptr_eq(current) => {}
+            (Some(scope), _) => {
+                self.commands.entity(child.entity).insert(scope.clone());
+                child.scope = Some(scope.clone());
+            }
+            (None, Some(_)) => {
+                self.commands.entity(child.entity).remove::<ContextScope>();
+                child.scope = None;
             }
         }
-        // uid not found, this child is considered to have newly appeared.
-        false
     }
 
-    fn remove(&mut self, count: usize) {
-        if count > 0 {
-            let mut i = self.cursor;
-            for _ in 0..count {
-                self.transition
-                    .remove(self.commands.entity(self.children[i].entity));
-                i += self.children[i].size;
+    /// Remove the child in `slot` from the node in `parent`, along with all of it's descendants.
+    fn remove(&mut self, parent: usize, slot: usize) {
+        let uid = self.tree.nodes[slot].uid;
+        let entity = self.tree.nodes[slot].entity;
+
+        let index = &mut self.tree.nodes[parent].index;
+        if index.get(&uid) == Some(&slot) {
+            index.remove(&uid);
+        }
+        // an entity that was despawned by something else can't run it's transition, but it's
+        // descendants that still exist must be despawned along with it.
+        let mut descendants = Vec::new();
+        if self.commands.get_entity(entity).is_none() {
+            let mut stack = self.tree.nodes[slot].children.clone();
+            while let Some(slot) = stack.pop() {
+                let entity = self.tree.nodes[slot].entity;
+                if self.commands.get_entity(entity).is_some() {
+                    descendants.push(entity);
+                } else {
+                    stack.extend(self.tree.nodes[slot].children.iter().copied());
+                }
+            }
+        }
+
+        // unmount effects are queued before the transition, so they still see the entities.
+        self.tree.release(slot, self.commands);
+        match self.commands.get_entity(entity) {
+            Some(mut entity) => {
+                entity.insert(Removed);
+                self.transition.remove(entity);
+            }
+            None => {
+                for entity in descendants {
+                    self.commands.entity(entity).despawn_recursive();
+                }
             }
-            self.children.drain(self.cursor..i);
-            self.len -= count;
-            self.changed = true;
         }
     }
 
-    /// Spawn and insert a new entity at `self.cursor`.
-    fn insert<'b, B>(&'b mut self, uid: u64, bundle: B) -> Shadow<'b, 'w, 's>
+    /// Spawn a new entity and append it to the children of this node.
+    fn insert<'b, B>(
+        &'b mut self,
+        uid: u64,
+        bundle: B,
+        location: &'static Location<'static>,
+    ) -> Shadow<'b, 'w, 's>
     where
         B: Bundle,
     {
         let mut entity = self.commands.spawn(bundle);
         self.transition.insert(&mut entity, self.transition_root);
-        self.children
-            .insert(self.cursor, Child::new(uid, entity.id()));
-        self.len += 1;
-        self.changed = true;
-        self.inner(self.children[self.cursor].entity, false)
+        let entity = entity.id();
+
+        let level = self.level.as_mut().unwrap();
+        level.open(self.tree);
+        let slot = self.tree.alloc(uid, entity, location);
+        self.tree.nodes[slot].bundle = Some(BundleType::of::<B>());
+        self.tree.stats.spawned += 1;
+        self.tree.nodes[level.node].index.insert(uid, slot);
+        level.children.push(slot);
+        self.attach_scope(slot);
+        self.inner(slot, false, Status::Inserted)
     }
 
-    fn inner<'b>(&'b mut self, parent: Entity, root: bool) -> Shadow<'b, 'w, 's> {
-        self.count += 1;
+    /// Remember that the node in `slot` was updated with a `B`. If it had a bundle of another type,
+    /// the components of that bundle that `B` doesn't have are removed.
+    fn set_bundle<B: Bundle>(&mut self, slot: usize) {
+        let node = &mut self.tree.nodes[slot];
+        let bundle = BundleType::of::<B>();
+        if let Some(previous) = node.bundle.replace(bundle) {
+            // `spawn_diff` spawns it's nodes without a bundle.
+            if previous.id != bundle.id && previous.id != TypeId::of::<()>() {
+                self.commands.add(RemoveStale {
+                    entity: node.entity,
+                    previous,
+                    bundle: bundle.id,
+                });
+            }
+        }
+    }
 
+    fn inner<'b>(&'b mut self, slot: usize, root: bool, status: Status) -> Shadow<'b, 'w, 's> {
+        self.tree.stats.visited += 1;
+        let mut level = Level::new(slot);
+        level.status = status;
         Shadow {
-            begin: self.cursor + 1,
-            cursor: self.cursor + 1,
-            len: self.children[self.cursor].count,
-            count: 0,
-            changed: false,
+            level: Some(level),
 
-            parent: Some(parent),
+            parent: Some(self.tree.nodes[slot].entity),
             commands: self.commands,
-            children: self.children,
-            parent_cursor: &mut self.cursor,
+            tree: self.tree,
 
             transition: self.transition,
             transition_root: root,
+
+            context: self.context,
+            context_scope: None,
+            key: 0,
         }
     }
 }
 
 impl<'a, 'w, 's> Drop for Shadow<'a, 'w, 's> {
     fn drop(&mut self) {
-        self.remove(self.len - self.count);
-
-        if self.changed {
-            let size = self.cursor - *self.parent_cursor;
-            self.children[*self.parent_cursor].size = size;
-            self.children[*self.parent_cursor].count = self.count;
-
-            if let Some(parent) = self.parent {
-                let mut i = self.begin;
-                let mut children = smallvec![];
-                for _ in 0..self.count {
-                    children.push(self.children[i].entity);
-                    i += self.children[i].size;
+        let Some(mut level) = self.level.take() else {
+            return;
+        };
+        if level.previous.is_none() && self.tree.nodes[level.node].children.is_empty() {
+            return;
+        }
+        level.open(self.tree);
+        let previous = level.previous.take().unwrap();
+
+        if level.reparent || level.children != previous {
+            self.tree.stats.reordered += 1;
+            // children that were not visited in this frame have disappeared
+            for &slot in previous.iter() {
+                if self.tree.nodes[slot].frame != self.tree.frame {
+                    self.remove(level.node, slot);
                 }
+            }
+
+            if level.node == 0 {
+                // the owner of the tree decides how the root is inserted in the mount.
+                self.tree.reorder = true;
+            } else if let Some(parent) = self.parent {
+                let children = level
+                    .children
+                    .iter()
+                    .map(|&slot| self.tree.nodes[slot].entity)
+                    .collect();
                 self.commands
                     .add(InsertChildrenInOrder { parent, children });
+                self.tree.stats.ordered += 1;
+            }
+            if level.reparent && self.parent.is_none() {
+                for &slot in level.children.iter() {
+                    self.commands
+                        .entity(self.tree.nodes[slot].entity)
+                        .remove_parent();
+                }
             }
         }
 
-        *self.parent_cursor = self.cursor;
+        // memos that were not visited in this frame may refer to removed children.
+        let frame = self.tree.frame;
+        let node = &mut self.tree.nodes[level.node];
+        if !node.memos.is_empty() {
+            node.memos.retain(|_, memo| memo.frame == frame);
+        }
+        node.children = level.children;
+    }
+}
+
+impl Level {
+    fn new(node: usize) -> Self {
+        Self {
+            node,
+            previous: None,
+            children: Vec::new(),
+            cursor: 0,
+            reparent: false,
+            status: Status::Kept,
+            scopes: 0,
+        }
+    }
+
+    /// Take the children of the previous frame from the node, if that didn't happen yet.
+    fn open(&mut self, tree: &mut Container) {
+        if self.previous.is_none() {
+            let previous = std::mem::take(&mut tree.nodes[self.node].children);
+            self.children.reserve(previous.len());
+            self.previous = Some(previous);
+        }
     }
 }
 
 impl Child {
-    fn new(uid: u64, entity: Entity) -> Self {
+    fn new(uid: u64, entity: Entity, frame: u32, location: &'static Location<'static>) -> Self {
         Self {
-            entity,
             uid,
-            size: 1,
-            count: 0,
+            entity,
+            location,
+            frame,
+            children: Vec::new(),
+            index: HashMap::default(),
+            memos: HashMap::default(),
+            state: HashMap::default(),
+            unmount: Vec::new(),
+            refs: Vec::new(),
+            scopes: Vec::new(),
+            scope: None,
+            bundle: None,
         }
     }
+
+    /// The root node, or a free slot.
+    fn placeholder() -> Self {
+        Self::new(0, Entity::PLACEHOLDER, 0, Location::caller())
+    }
+}
+
+impl<T> NodeState<T> {
+    /// Lock the state for reading or writing.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn get(&self) -> T
+    where
+        T: Clone,
+    {
+        self.lock().clone()
+    }
+
+    pub fn set(&self, value: T) {
+        *self.lock() = value;
+    }
+}
+
+impl NodeRef {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The entity of the node, if it currently exists.
+    pub fn get(&self) -> Option<Entity> {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set(&self, entity: Option<Entity>) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = entity;
+    }
+
+    /// Empty the ref, unless it was pointed to another entity in the meantime.
+    fn release(&self, entity: Entity) {
+        let mut current = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if *current == Some(entity) {
+            *current = None;
+        }
+    }
+}
+
+impl<T> Clone for NodeState<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
 }
 
 impl Command for InsertChildrenInOrder {
     fn apply(self, world: &mut World) {
-        let mut parent = world.entity_mut(self.parent);
-
-        if let Some(existing) = parent
-            .get::<Children>()
-            .map(|c| c.iter().copied().collect::<SmallVec<[Entity; 8]>>())
-        {
-            let mut offset = 0;
-            for new in self.children {
-                if let Some(i) = existing[offset..].iter().position(|&e| e == new) {
-                    offset += i;
+        // entities can be despawned by other systems, see `ShadowValidation`.
+        if world.get_entity(self.parent).is_none() {
+            return;
+        }
+        let existing: HashSet<Entity> = world
+            .get::<Children>(self.parent)
+            .map(|c| c.iter().copied().collect())
+            .unwrap_or_default();
+        let missing: SmallVec<[Entity; 8]> = self
+            .children
+            .iter()
+            .copied()
+            .filter(|&e| !existing.contains(&e) && world.get_entity(e).is_some())
+            .collect();
+
+        if !missing.is_empty() {
+            world.entity_mut(self.parent).push_children(&missing);
+        }
+        let Some(children) = world.get::<Children>(self.parent) else {
+            return;
+        };
+
+        // managed children are sorted by their shadow order. foreign children are placed by the
+        // policy of the parent, children that are still running their remove transition stick to
+        // the managed child before them.
+        let policy = world
+            .get::<ForeignChildren>(self.parent)
+            .copied()
+            .unwrap_or_default();
+        let rank: HashMap<Entity, usize> = self
+            .children
+            .iter()
+            .enumerate()
+            .map(|(i, &e)| (e, 2 * i + 2))
+            .collect();
+        let mut anchor = 0;
+        let mut sorted = true;
+        let keys: HashMap<Entity, usize> = children
+            .iter()
+            .map(|&e| {
+                let key = match rank.get(&e) {
+                    Some(&rank) => rank,
+                    None if world.get::<Removed>(e).is_some() => anchor + 1,
+                    None => match policy {
+                        ForeignChildren::Preserve => anchor + 1,
+                        ForeignChildren::Before => 0,
+                        ForeignChildren::After => usize::MAX,
+                    },
+                };
+                sorted &= key >= anchor;
+                anchor = key & !1;
+                (e, key)
+            })
+            .collect();
+
+        if !sorted {
+            let mut parent = world.entity_mut(self.parent);
+            let mut children = parent.get_mut::<Children>().unwrap();
+            children.sort_by_cached_key(|e| keys[e]);
+        }
+    }
+}
+
+impl Command for RunEffect {
+    fn apply(self, world: &mut World) {
+        if world.get_entity(self.entity).is_none() {
+            return;
+        }
+        self.effect.handle_for(world, self.entity, self.entity);
+        self.effect.apply(world);
+    }
+}
+
+impl Container {
+    /// Reconcile the tree with `fragment`, with the root nodes as children of `mount`.
+    /// Returns `true` if the root nodes must be inserted in `mount` again.
+    pub(crate) fn update<F>(
+        &mut self,
+        commands: &mut Commands,
+        mount: Option<Entity>,
+        transition: &dyn Transition,
+        fragment: F,
+    ) -> bool
+    where
+        F: FnOnce(&mut Shadow),
+    {
+        self.frame = self.frame.wrapping_add(1);
+        self.reorder = false;
+        if !self.duplicates.is_empty() {
+            self.duplicates.clear();
+        }
+
+        let mut level = Level::new(0);
+        level.reparent = self.mount != mount;
+        self.mount = mount;
+
+        let mut updater = Shadow {
+            level: Some(level),
+
+            parent: mount,
+            commands,
+            tree: self,
+
+            transition,
+            transition_root: true,
+
+            context: None,
+            context_scope: None,
+            key: 0,
+        };
+        fragment(&mut updater);
+        drop(updater);
+
+        let reorder = self.reorder && mount.is_some();
+        if reorder {
+            self.stats.ordered += 1;
+        }
+        reorder
+    }
+
+    /// The work done since the stats were last taken.
+    pub(crate) fn take_stats(&mut self) -> ReconcileStats {
+        std::mem::take(&mut self.stats)
+    }
+
+    /// The entity the root nodes are mounted in.
+    pub(crate) fn mount(&self) -> Option<Entity> {
+        self.mount
+    }
+
+    /// The entities of the root nodes, in order.
+    pub(crate) fn roots(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.nodes[0]
+            .children
+            .iter()
+            .map(|&slot| self.nodes[slot].entity)
+    }
+
+    /// Remove all root nodes through `transition`, and start over with an empty tree.
+    pub(crate) fn clear(
+        &mut self,
+        commands: &mut Commands,
+        entities: &Query<()>,
+        transition: &dyn Transition,
+    ) {
+        for child in self.nodes.iter_mut() {
+            for effect in child.unmount.drain(..) {
+                commands.add(RunEffect {
+                    effect,
+                    entity: child.entity,
+                });
+            }
+            for node_ref in child.refs.iter() {
+                node_ref.release(child.entity);
+            }
+        }
+        for entity in self.roots() {
+            if entities.contains(entity) {
+                let mut entity = commands.entity(entity);
+                entity.insert(Removed);
+                transition.remove(entity);
+            }
+        }
+        *self = Container::default();
+    }
+
+    /// Queue the mount and update effects of the last update.
+    /// Called by the owner of the tree after the root nodes have been inserted in the mount.
+    pub(crate) fn run_effects(&mut self, commands: &mut Commands) {
+        for (entity, effect) in self.effects.drain(..) {
+            commands.add(RunEffect { effect, entity });
+        }
+    }
+
+    /// Despawn whatever is left of the tree, and start over with an empty one.
+    /// Unmount effects are queued before the despawn, they run for the entities that still exist.
+    pub(crate) fn forget(&mut self, commands: &mut Commands, entities: &Query<()>) {
+        for child in self.nodes.iter_mut() {
+            for effect in child.unmount.drain(..) {
+                commands.add(RunEffect {
+                    effect,
+                    entity: child.entity,
+                });
+            }
+            for node_ref in child.refs.iter() {
+                node_ref.release(child.entity);
+            }
+        }
+        for entity in self.roots() {
+            if entities.contains(entity) {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        *self = Container::default();
+    }
+
+    /// Compare the tree with the entity hierarchy, and add the nodes that don't match to
+    /// `problems`.
+    pub(crate) fn validate(&self, nodes: &ValidationNodes, problems: &mut Vec<Problem>) {
+        let mut stack = vec![(0, self.mount)];
+        while let Some((slot, entity)) = stack.pop() {
+            let node = &self.nodes[slot];
+            for &child in node.children.iter() {
+                let child = &self.nodes[child];
+                let problem = |kind| Problem {
+                    kind,
+                    entity: child.entity,
+                    node: Some((child.uid, child.location)),
+                };
+                let Ok((parent, ..)) = nodes.get(child.entity) else {
+                    problems.push(problem(ProblemKind::Despawned));
+                    continue;
+                };
+                if entity.is_some() && parent.map(Parent::get) != entity {
+                    problems.push(problem(ProblemKind::Moved));
+                }
+            }
+            stack.extend(
+                node.children
+                    .iter()
+                    .map(|&child| (child, Some(self.nodes[child].entity))),
+            );
+
+            let Some(entity) = entity else {
+                continue;
+            };
+            let Ok((_, Some(children), _, policy)) = nodes.get(entity) else {
+                continue;
+            };
+            let problem = |kind| Problem {
+                kind,
+                entity,
+                node: (slot != 0).then_some((node.uid, node.location)),
+            };
+
+            let managed: HashSet<Entity> = node
+                .children
+                .iter()
+                .map(|&child| self.nodes[child].entity)
+                .collect();
+            let expected = node
+                .children
+                .iter()
+                .map(|&child| self.nodes[child].entity)
+                .filter(|child| children.contains(child));
+            let actual = children
+                .iter()
+                .copied()
+                .filter(|child| managed.contains(child));
+            if !actual.eq(expected) {
+                problems.push(problem(ProblemKind::OutOfOrder));
+            }
+
+            // the mount may hold the roots of other trees, or anything else. foreign children are
+            // expected where the parent has a policy for them.
+            if slot != 0 && policy.is_none() {
+                for &child in children.iter() {
+                    // despawned children can linger in `Children`, removed ones are leaving.
+                    let foreign = matches!(nodes.get(child), Ok((_, _, None, _)));
+                    if foreign && !managed.contains(&child) {
+                        problems.push(problem(ProblemKind::Foreign(child)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drop the nodes of `entities` from the tree, along with their descendants, so they are
+    /// spawned again by the next update. Descendants that still exist are despawned.
+    pub(crate) fn drop_nodes(
+        &mut self,
+        entities: &[Entity],
+        commands: &mut Commands,
+        existing: &Query<()>,
+    ) {
+        let mut parents = HashMap::new();
+        let mut stack = vec![0];
+        while let Some(slot) = stack.pop() {
+            for &child in self.nodes[slot].children.iter() {
+                parents.insert(child, slot);
+                stack.push(child);
+            }
+        }
+
+        for (&slot, &parent) in parents.iter() {
+            if !entities.contains(&self.nodes[slot].entity) {
+                continue;
+            }
+            let uid = self.nodes[slot].uid;
+            let node = &mut self.nodes[parent];
+            node.children.retain(|&child| child != slot);
+            if node.index.get(&uid) == Some(&slot) {
+                node.index.remove(&uid);
+            }
+            // memoized fragments above the node would skip it, instead of spawning it again.
+            let mut ancestor = Some(parent);
+            while let Some(slot) = ancestor {
+                self.nodes[slot].memos.clear();
+                ancestor = parents.get(&slot).copied();
+            }
+
+            // descendants that still exist are despawned along with their own descendants.
+            let mut descendants = Vec::new();
+            let mut stack = self.nodes[slot].children.clone();
+            while let Some(slot) = stack.pop() {
+                let entity = self.nodes[slot].entity;
+                if existing.contains(entity) {
+                    descendants.push(entity);
                 } else {
-                    parent.insert_children(offset, &[new]);
+                    stack.extend(self.nodes[slot].children.iter().copied());
                 }
-                offset += 1;
             }
-        } else {
-            parent.push_children(&self.children);
+            self.release(slot, commands);
+            for entity in descendants {
+                commands.entity(entity).despawn_recursive();
+            }
         }
     }
-}
 
-impl Container {
-    fn len(&self) -> usize {
-        self.0[0].count
+    fn alloc(&mut self, uid: u64, entity: Entity, location: &'static Location<'static>) -> usize {
+        let child = Child::new(uid, entity, self.frame, location);
+        if let Some(slot) = self.free.pop() {
+            self.nodes[slot] = child;
+            slot
+        } else {
+            self.nodes.push(child);
+            self.nodes.len() - 1
+        }
     }
 
-    fn get(&mut self) -> &mut Vec<Child> {
-        &mut self.0
+    /// Free the slot of a node and all of it's descendants, and queue their unmount effects.
+    fn release(&mut self, slot: usize, commands: &mut Commands) {
+        let mut stack = vec![slot];
+        while let Some(slot) = stack.pop() {
+            let child = std::mem::replace(&mut self.nodes[slot], Child::placeholder());
+            for effect in child.unmount {
+                commands.add(RunEffect {
+                    effect,
+                    entity: child.entity,
+                });
+            }
+            for node_ref in child.refs {
+                node_ref.release(child.entity);
+            }
+            stack.extend(child.children);
+            self.free.push(slot);
+            self.stats.removed += 1;
+        }
     }
 }
 
-impl FromWorld for Container {
-    fn from_world(_: &mut World) -> Self {
-        Container(vec![Child {
-            count: 0,
-            size: 1,
-            entity: Entity::PLACEHOLDER,
-            uid: 0,
-        }])
+impl Default for Container {
+    fn default() -> Self {
+        Container {
+            nodes: vec![Child::placeholder()],
+            free: Vec::new(),
+            frame: 0,
+            mount: None,
+            reorder: false,
+            effects: Vec::new(),
+            duplicates: HashMap::default(),
+            stats: ReconcileStats::default(),
+        }
     }
 }
 
+/// Derive a uid from `uid` and `key`.
+fn mix(uid: u64, key: u64) -> u64 {
+    let mut h = StableHasher::new();
+    h.write_u64(uid);
+    h.write_u64(key);
+    h.finish()
+}
+
+/// A `Key` unique to the location the macro is used at, optionally combined with a hashable value.
+/// The location is identified by module path, line and column, so it doesn't depend on where the
+/// crate is built.
 #[macro_export]
 macro_rules! id {
     () => {{
-        use std::hash::Hasher;
-        let mut h = std::collections::hash_map::DefaultHasher::new();
-        h.write(file!().as_bytes());
-        h.write_u32(line!());
-        h.write_u32(column!());
-        h.finish()
+        const KEY: $crate::Key =
+            $crate::Key::from_static(concat!(module_path!(), ":", line!(), ":", column!()));
+        KEY
     }};
 
-    ($hashable:expr) => {{
-        use std::hash::{Hash, Hasher};
-        let mut h = std::collections::hash_map::DefaultHasher::new();
-        h.write(file!().as_bytes());
-        h.write_u32(line!());
-        h.write_u32(column!());
-        $hashable.hash(&mut h);
-        h.finish()
-    }};
+    ($hashable:expr) => {
+        $crate::id!().with(&$hashable)
+    };
 }
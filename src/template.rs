@@ -0,0 +1,478 @@
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, HandleId, LoadContext, LoadedAsset},
+    ecs::system::{CommandQueue, EntityCommands, SystemState},
+    prelude::*,
+    reflect::{GetPath, TypePath, TypeRegistryInternal, TypeUuid},
+    utils::{HashMap, HashSet},
+};
+use ron::extensions::Extensions;
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::base_handler::{Handler, HandlerImpl};
+use crate::interaction_handler::{
+    InteractionFilter, InteractionHandler, OnClick, OnClickEnd, OnHover, OnHoverEnd,
+};
+use crate::key::Key;
+use crate::scene::ShadowMount;
+use crate::shadow::Shadow;
+use crate::{HandlerSchedule, ShadowSystem};
+
+/// A layout authored in RON, loaded from `.ui.ron` files.
+///
+/// ```text
+/// (
+///     nodes: [
+///         (
+///             id: "panel",
+///             style: (flex_direction: Column, width: Px(256.0)),
+///             background: Rgba(red: 0.5, green: 0.5, blue: 0.5, alpha: 1.0),
+///             children: [
+///                 (id: "count", kind: Text, text: "count: {Counter.value}"),
+///                 (id: "up", kind: Button, on_click: "up", children: [(kind: Text, text: "up")]),
+///             ],
+///         ),
+///     ],
+/// )
+/// ```
+///
+/// Nodes are identified by their `id`, or by their position if they don't have one. Give nodes
+/// an `id` so they keep their entity and state when nodes are added or removed before them.
+#[derive(Deserialize, TypeUuid, TypePath, Clone, Debug, Default)]
+#[uuid = "5b8e3e43-62a1-4a4b-9f0c-0a9d1c5ab3e2"]
+#[serde(default)]
+pub struct UiTemplate {
+    pub nodes: Vec<TemplateNode>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct TemplateNode {
+    pub id: Option<String>,
+    pub kind: TemplateKind,
+    pub style: TemplateStyle,
+    pub background: Option<Color>,
+    /// Text of a `Text` node. `{Resource.path}` is replaced with the value at `path` in the
+    /// resource named `Resource`, which must be registered for reflection with
+    /// `#[reflect(Resource)]`. Use `{{` and `}}` for literal braces.
+    pub text: Option<String>,
+    /// Path of the font asset of a `Text` node.
+    pub font: Option<String>,
+    pub font_size: Option<f32>,
+    pub color: Option<Color>,
+    /// Names of handlers added with `add_template_handler`.
+    pub on_click: Option<String>,
+    pub on_click_end: Option<String>,
+    pub on_hover: Option<String>,
+    pub on_hover_end: Option<String>,
+    pub children: Vec<TemplateNode>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TemplateKind {
+    #[default]
+    Node,
+    Button,
+    Text,
+}
+
+macro_rules! template_style {
+    ($($field:ident: $ty:ty),* $(,)?) => {
+        /// The fields of `Style` that a template can set. Fields that are left out keep their
+        /// default value.
+        #[derive(Deserialize, Clone, Debug, Default)]
+        #[serde(default)]
+        pub struct TemplateStyle {
+            $(pub $field: Option<$ty>,)*
+            pub aspect_ratio: Option<f32>,
+        }
+
+        impl TemplateStyle {
+            pub fn to_style(&self) -> Style {
+                let mut style = Style::default();
+                $(if let Some(value) = self.$field {
+                    style.$field = value.into();
+                })*
+                style.aspect_ratio = self.aspect_ratio;
+                style
+            }
+        }
+    };
+}
+
+template_style! {
+    display: Display,
+    position_type: PositionType,
+    overflow: Overflow,
+    direction: Direction,
+    left: Val,
+    right: Val,
+    top: Val,
+    bottom: Val,
+    width: Val,
+    height: Val,
+    min_width: Val,
+    min_height: Val,
+    max_width: Val,
+    max_height: Val,
+    align_items: AlignItems,
+    justify_items: JustifyItems,
+    align_self: AlignSelf,
+    justify_self: JustifySelf,
+    align_content: AlignContent,
+    justify_content: JustifyContent,
+    margin: TemplateRect,
+    padding: TemplateRect,
+    border: TemplateRect,
+    flex_direction: FlexDirection,
+    flex_wrap: FlexWrap,
+    flex_grow: f32,
+    flex_shrink: f32,
+    flex_basis: Val,
+    row_gap: Val,
+    column_gap: Val,
+}
+
+/// A `UiRect` in a template. Sides that are left out are zero.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct TemplateRect {
+    pub left: Val,
+    pub right: Val,
+    pub top: Val,
+    pub bottom: Val,
+}
+
+impl Default for TemplateRect {
+    fn default() -> Self {
+        let UiRect {
+            left,
+            right,
+            top,
+            bottom,
+        } = UiRect::default();
+        Self {
+            left,
+            right,
+            top,
+            bottom,
+        }
+    }
+}
+
+impl From<TemplateRect> for UiRect {
+    fn from(rect: TemplateRect) -> Self {
+        UiRect::new(rect.left, rect.right, rect.top, rect.bottom)
+    }
+}
+
+/// Renders a `UiTemplate` as the children of the entity it's attached to.
+///
+/// The template is reconciled every frame, and again from scratch when the asset changes, so
+/// edits on disk show up without restarting. Nodes that are still in the edited template keep
+/// their entities and state.
+#[derive(Component, Default)]
+pub struct ShadowTemplate {
+    pub template: Handle<UiTemplate>,
+    mount: ShadowMount,
+    /// The template that was rendered last.
+    rendered: Option<HandleId>,
+    /// The text of every text node, by path, to update nodes when their bindings change.
+    texts: HashMap<u64, String>,
+}
+
+/// Loads `UiTemplate`s from `.ui.ron` files.
+#[derive(Default)]
+pub struct UiTemplateLoader;
+
+/// Handlers that templates can refer to by name.
+#[derive(Resource, Default)]
+pub(crate) struct TemplateHandlers {
+    named: HashMap<String, Arc<dyn Handler<In = (), Out = ()>>>,
+}
+
+/// Adds the `UiTemplate` asset and renders `ShadowTemplate`s in `ShadowSystem::Reconcile` of the
+/// handler schedule. Requires the `AssetPlugin`, and panics if it's added before the
+/// `ShadowScenePlugin`.
+pub struct ShadowTemplatePlugin;
+
+/// Extension methods for registering the handlers of templates.
+pub trait ShadowTemplateAppExt {
+    /// Make `handler` available to templates as `name`, for example as `on_click: "name"`.
+    fn add_template_handler<T, M>(&mut self, name: impl Into<String>, handler: T) -> &mut Self
+    where
+        T: SystemParamFunction<M, In = (), Out = ()>,
+        M: 'static;
+}
+
+struct Renderer<'a> {
+    world: &'a World,
+    registry: Option<&'a TypeRegistryInternal>,
+    handlers: Option<&'a TemplateHandlers>,
+    texts: &'a mut HashMap<u64, String>,
+    reload: bool,
+}
+
+impl ShadowTemplate {
+    pub fn new(template: Handle<UiTemplate>) -> Self {
+        Self {
+            template,
+            ..default()
+        }
+    }
+
+    pub(crate) fn mount_mut(&mut self) -> &mut ShadowMount {
+        &mut self.mount
+    }
+}
+
+impl Plugin for ShadowTemplatePlugin {
+    fn build(&self, app: &mut App) {
+        let schedule = HandlerSchedule::of(app, "the `ShadowTemplatePlugin`");
+        app.add_asset::<UiTemplate>()
+            .init_asset_loader::<UiTemplateLoader>()
+            .init_resource::<TemplateHandlers>()
+            .add_systems(schedule, template_system.in_set(ShadowSystem::Reconcile));
+    }
+}
+
+impl ShadowTemplateAppExt for App {
+    fn add_template_handler<T, M>(&mut self, name: impl Into<String>, handler: T) -> &mut Self
+    where
+        T: SystemParamFunction<M, In = (), Out = ()>,
+        M: 'static,
+    {
+        self.world
+            .get_resource_or_insert_with(TemplateHandlers::default)
+            .named
+            .insert(name.into(), Arc::new(HandlerImpl::new(handler)));
+        self
+    }
+}
+
+impl AssetLoader for UiTemplateLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let template: UiTemplate = ron::Options::default()
+                .with_default_extension(Extensions::IMPLICIT_SOME)
+                .from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(template));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ui.ron"]
+    }
+}
+
+impl<'a> Renderer<'a> {
+    fn render(&mut self, shadow: &mut Shadow, nodes: &[TemplateNode], path: u64) {
+        for (index, node) in nodes.iter().enumerate() {
+            let key = match &node.id {
+                Some(id) => Key::from(id.clone()),
+                None => Key::from(index),
+            };
+            let path = Key::new(&path).with(&key.uid()).uid();
+
+            let text = node.text.as_deref().map(|text| self.resolve(text));
+            let changed = match &text {
+                Some(text) => self.texts.insert(path, text.clone()).as_ref() != Some(text),
+                None => false,
+            };
+
+            let mut built = false;
+            let update = self.reload || changed;
+            let mut child = match node.kind {
+                TemplateKind::Node => shadow.spawn_dyn(key, update, || {
+                    built = true;
+                    NodeBundle {
+                        style: node.style.to_style(),
+                        background_color: node.background.unwrap_or(Color::NONE).into(),
+                        ..default()
+                    }
+                }),
+                TemplateKind::Button => shadow.spawn_dyn(key, update, || {
+                    built = true;
+                    ButtonBundle {
+                        style: node.style.to_style(),
+                        background_color: node.background.unwrap_or(Color::WHITE).into(),
+                        ..default()
+                    }
+                }),
+                TemplateKind::Text => shadow.spawn_dyn(key, update, || {
+                    built = true;
+                    TextBundle {
+                        style: node.style.to_style(),
+                        background_color: node.background.unwrap_or(Color::NONE).into(),
+                        text: Text::from_section(text.unwrap_or_default(), self.text_style(node)),
+                        ..default()
+                    }
+                }),
+            };
+
+            if built {
+                if let Some(entity) = child.entity() {
+                    let mut entity = child.commands().entity(entity);
+                    self.set_handler::<OnClick>(&mut entity, &node.on_click);
+                    self.set_handler::<OnClickEnd>(&mut entity, &node.on_click_end);
+                    self.set_handler::<OnHover>(&mut entity, &node.on_hover);
+                    self.set_handler::<OnHoverEnd>(&mut entity, &node.on_hover_end);
+                }
+            }
+
+            child.with(|shadow| self.render(shadow, &node.children, path));
+        }
+    }
+
+    fn text_style(&self, node: &TemplateNode) -> TextStyle {
+        let mut style = TextStyle::default();
+        if let Some(font) = &node.font {
+            style.font = self.world.resource::<AssetServer>().load(font.as_str());
+        }
+        if let Some(font_size) = node.font_size {
+            style.font_size = font_size;
+        }
+        if let Some(color) = node.color {
+            style.color = color;
+        }
+        style
+    }
+
+    fn set_handler<F: InteractionFilter>(
+        &self,
+        entity: &mut EntityCommands,
+        name: &Option<String>,
+    ) {
+        let Some(name) = name else {
+            entity.remove::<InteractionHandler<F>>();
+            return;
+        };
+        match self.handlers.and_then(|handlers| handlers.named.get(name)) {
+            Some(handler) => {
+                entity.insert(InteractionHandler::<F>::from_handler(handler.clone()));
+            }
+            None => {
+                warn!("template handler `{}` was not added to the app", name);
+                entity.remove::<InteractionHandler<F>>();
+            }
+        }
+    }
+
+    /// Replace the bindings in `text` with the values they point to.
+    fn resolve(&self, text: &str) -> String {
+        let mut resolved = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find(['{', '}']) {
+            resolved.push_str(&rest[..start]);
+            let brace = &rest[start..start + 1];
+            rest = &rest[start + 1..];
+            if rest.starts_with(brace) {
+                resolved.push_str(brace);
+                rest = &rest[1..];
+                continue;
+            }
+            if brace == "}" {
+                resolved.push_str(brace);
+                continue;
+            }
+            let Some(end) = rest.find('}') else {
+                resolved.push('{');
+                continue;
+            };
+            match self.binding(&rest[..end]) {
+                Some(value) => resolved.push_str(&value),
+                None => {
+                    resolved.push('{');
+                    resolved.push_str(&rest[..end + 1]);
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+        resolved.push_str(rest);
+        resolved
+    }
+
+    /// The value of `binding`, in the form `Resource.path`.
+    fn binding(&self, binding: &str) -> Option<String> {
+        let (resource, path) = binding.split_once('.').unwrap_or((binding, ""));
+        let registry = self.registry?;
+        let registration = registry
+            .get_with_short_name(resource)
+            .or_else(|| registry.get_with_name(resource))?;
+        let resource = registration
+            .data::<ReflectResource>()?
+            .reflect(self.world)?;
+        let value = if path.is_empty() {
+            resource
+        } else {
+            resource.reflect_path(path).ok()?
+        };
+        Some(match value.downcast_ref::<String>() {
+            Some(value) => value.clone(),
+            None => format!("{:?}", value),
+        })
+    }
+}
+
+type TemplateState = SystemState<(
+    EventReader<'static, 'static, AssetEvent<UiTemplate>>,
+    Query<'static, 'static, Entity, With<ShadowTemplate>>,
+)>;
+
+fn template_system(world: &mut World, state: &mut TemplateState) {
+    let (mut events, templates) = state.get(world);
+    let mut modified = HashSet::new();
+    for event in events.iter() {
+        if let AssetEvent::Modified { handle } = event {
+            modified.insert(handle.id());
+        }
+    }
+    let templates: Vec<Entity> = templates.iter().collect();
+
+    for entity in templates {
+        let mut template = world.get_mut::<ShadowTemplate>(entity).unwrap();
+        let handle = template.template.clone_weak();
+        let mut mount = std::mem::take(&mut template.mount);
+        let mut texts = std::mem::take(&mut template.texts);
+        let reload = modified.contains(&handle.id()) || template.rendered != Some(handle.id());
+        if reload {
+            texts.clear();
+        }
+
+        let mut queue = CommandQueue::default();
+        let rendered = {
+            let world: &World = world;
+            match world.resource::<Assets<UiTemplate>>().get(&handle) {
+                Some(asset) => {
+                    let registry = world.get_resource::<AppTypeRegistry>().map(|r| r.read());
+                    let mut renderer = Renderer {
+                        world,
+                        registry: registry.as_deref(),
+                        handlers: world.get_resource::<TemplateHandlers>(),
+                        texts: &mut texts,
+                        reload,
+                    };
+                    let mut commands = Commands::new(&mut queue, world);
+                    mount.update(entity, &mut commands, |shadow| {
+                        renderer.render(shadow, &asset.nodes, 0);
+                    });
+                    Some(handle.id())
+                }
+                None => None,
+            }
+        };
+
+        let mut template = world.get_mut::<ShadowTemplate>(entity).unwrap();
+        template.mount = mount;
+        template.texts = texts;
+        if rendered.is_some() {
+            template.rendered = rendered;
+        }
+        queue.apply(world);
+    }
+}
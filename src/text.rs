@@ -0,0 +1,59 @@
+use bevy::{ecs::system::Command, prelude::*};
+
+/// Patch the sections of the `Text` of `entity` in place.
+pub(crate) struct PatchText {
+    pub entity: Entity,
+    pub sections: Vec<TextSection>,
+}
+
+/// The sections a text node was last patched with.
+#[derive(Default)]
+pub(crate) struct TextSections(pub Vec<TextSection>);
+
+impl Command for PatchText {
+    fn apply(self, world: &mut World) {
+        let Some(mut entity) = world.get_entity_mut(self.entity) else {
+            return;
+        };
+        let Some(mut text) = entity.get_mut::<Text>() else {
+            entity.insert(Text::from_sections(self.sections));
+            return;
+        };
+
+        let live = text.bypass_change_detection();
+        let mut changed = live.sections.len() != self.sections.len();
+        live.sections.truncate(self.sections.len());
+        for (index, section) in self.sections.into_iter().enumerate() {
+            let Some(live) = live.sections.get_mut(index) else {
+                live.sections.push(section);
+                continue;
+            };
+            if live.value != section.value {
+                live.value = section.value;
+                changed = true;
+            }
+            if !style_eq(&live.style, &section.style) {
+                live.style = section.style;
+                changed = true;
+            }
+        }
+        if changed {
+            text.set_changed();
+        }
+    }
+}
+
+impl TextSections {
+    pub fn matches(&self, sections: &[TextSection]) -> bool {
+        self.0.len() == sections.len()
+            && self
+                .0
+                .iter()
+                .zip(sections)
+                .all(|(a, b)| a.value == b.value && style_eq(&a.style, &b.style))
+    }
+}
+
+fn style_eq(a: &TextStyle, b: &TextStyle) -> bool {
+    a.font == b.font && a.font_size == b.font_size && a.color == b.color
+}
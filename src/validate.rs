@@ -0,0 +1,150 @@
+use bevy::{prelude::*, utils::HashSet};
+use std::panic::Location;
+
+use crate::scene::{ShadowMount, ShadowScenes};
+use crate::shadow::{Container, ForeignChildren};
+use crate::template::ShadowTemplate;
+
+/// Checks every frame that the entities of all shadow trees are still where their trees expect
+/// them to be, and reports the ones that were despawned, moved, reordered or given children by
+/// something other than their scene. Children are expected on parents with `ForeignChildren`.
+///
+/// Enabled by default in debug builds only. Despawned nodes are respawned while reconciling either
+/// way, validation also recovers nodes that aren't visited according to `recovery`.
+#[derive(Resource, Clone, Debug)]
+pub struct ShadowValidation {
+    pub enabled: bool,
+    pub recovery: ShadowRecovery,
+}
+
+/// What to do with nodes of which the entity was despawned by something other than their scene.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShadowRecovery {
+    /// Drop the node and it's descendants from the tree, so the next update spawns them again.
+    #[default]
+    Respawn,
+    /// Despawn the whole tree of the node, so the next update rebuilds it from scratch.
+    Forget,
+}
+
+/// Marks the entities of nodes that have been removed from their tree, while their remove
+/// transition runs.
+#[derive(Component)]
+pub(crate) struct Removed;
+
+/// A node of which the entity doesn't match it's tree.
+pub(crate) struct Problem {
+    pub kind: ProblemKind,
+    pub entity: Entity,
+    /// Uid and location of the node, `None` for the mount of a tree.
+    pub node: Option<(u64, &'static Location<'static>)>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum ProblemKind {
+    /// The entity of a node no longer exists.
+    Despawned,
+    /// The entity of a node has another parent than the node.
+    Moved,
+    /// The children of the entity are in another order than the children of the node.
+    OutOfOrder,
+    /// The entity has a child that isn't part of the tree.
+    Foreign(Entity),
+}
+
+pub(crate) type ValidationNodes<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Option<&'static Parent>,
+        Option<&'static Children>,
+        Option<&'static Removed>,
+        Option<&'static ForeignChildren>,
+    ),
+>;
+
+impl Default for ShadowValidation {
+    fn default() -> Self {
+        Self {
+            enabled: cfg!(debug_assertions),
+            recovery: ShadowRecovery::Respawn,
+        }
+    }
+}
+
+pub(crate) fn validation_system(
+    validation: Res<ShadowValidation>,
+    scenes: Option<ResMut<ShadowScenes>>,
+    (mut mounts, mut templates): (Query<&mut ShadowMount>, Query<&mut ShadowTemplate>),
+    nodes: ValidationNodes,
+    entities: Query<()>,
+    mut commands: Commands,
+    mut reported: Local<HashSet<(Entity, ProblemKind)>>,
+) {
+    if !validation.enabled {
+        return;
+    }
+
+    let mut seen = HashSet::default();
+    let mut check = |scene: &str, tree: &mut Container| {
+        let mut problems = Vec::new();
+        tree.validate(&nodes, &mut problems);
+
+        let mut despawned = Vec::new();
+        for problem in problems {
+            if problem.kind == ProblemKind::Despawned {
+                despawned.push(problem.entity);
+            }
+            let key = (problem.entity, problem.kind);
+            if !reported.contains(&key) {
+                problem.report(scene);
+            }
+            seen.insert(key);
+        }
+
+        if !despawned.is_empty() {
+            // the descendants of despawned nodes may be left without a parent, drop them first.
+            tree.drop_nodes(&despawned, &mut commands, &entities);
+            if validation.recovery == ShadowRecovery::Forget {
+                tree.forget(&mut commands, &entities);
+            }
+        }
+    };
+
+    if let Some(mut scenes) = scenes {
+        scenes.for_each_tree(|scene, tree| check(scene, tree));
+    }
+    // validating doesn't change the trees, unless they are recovered.
+    for mut mount in mounts.iter_mut() {
+        check("ShadowMount", mount.bypass_change_detection().tree_mut());
+    }
+    for mut template in templates.iter_mut() {
+        let template = template.bypass_change_detection();
+        check("ShadowTemplate", template.mount_mut().tree_mut());
+    }
+
+    *reported = seen;
+}
+
+impl Problem {
+    fn report(&self, scene: &str) {
+        let node = match self.node {
+            Some((uid, location)) => format!("node {uid:#x} spawned at {location}"),
+            None => format!("mount {:?}", self.entity),
+        };
+        match self.kind {
+            ProblemKind::Despawned => {
+                warn!("{node} of `{scene}` was despawned by something other than it's scene")
+            }
+            ProblemKind::Moved => warn!(
+                "{node} of `{scene}` was moved to another parent by something other than it's scene"
+            ),
+            ProblemKind::OutOfOrder => warn!(
+                "children of {node} of `{scene}` were reordered by something other than it's scene"
+            ),
+            ProblemKind::Foreign(child) => {
+                warn!("{node} of `{scene}` has a child {child:?} that isn't part of the scene")
+            }
+        }
+    }
+}
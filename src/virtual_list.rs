@@ -0,0 +1,167 @@
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+    ui::RelativeCursorPosition,
+    utils::HashMap,
+};
+use std::ops::Range;
+
+use crate::shadow::NodeState;
+
+/// A list of which only the rows in view are spawned, see `Shadow::virtual_list`.
+#[derive(Clone, Copy, Debug)]
+pub struct VirtualList {
+    /// The number of rows.
+    pub len: usize,
+    pub row_height: RowHeight,
+    /// The number of rows that are spawned above and below the rows in view.
+    pub overscan: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum RowHeight {
+    /// Every row is this high.
+    Fixed(f32),
+    /// Rows are measured once they're laid out, rows that weren't in view yet are assumed to be
+    /// this high.
+    Measured(f32),
+}
+
+/// Scroll state of a virtual list, shared between the list and `virtual_list_system`.
+#[derive(Default)]
+pub(crate) struct ListState {
+    /// The size of the list node.
+    viewport: Vec2,
+    offset: f32,
+    /// The height of all rows together.
+    height: f32,
+    row_height: f32,
+    /// The first row that is spawned.
+    first: usize,
+    /// Measured row heights, by index.
+    measured: HashMap<usize, f32>,
+}
+
+/// The rows that are spawned, and where they start.
+pub(crate) struct Window {
+    pub rows: Range<usize>,
+    pub top: f32,
+    pub height: f32,
+    pub offset: f32,
+}
+
+/// Marks the node of a virtual list.
+#[derive(Component)]
+pub(crate) struct VirtualListNode(pub NodeState<ListState>);
+
+/// Marks the node holding the rows of a virtual list.
+#[derive(Component)]
+pub(crate) struct VirtualListRows(pub NodeState<ListState>);
+
+impl VirtualList {
+    pub fn new(len: usize, row_height: RowHeight) -> Self {
+        Self {
+            len,
+            row_height,
+            overscan: 2,
+        }
+    }
+
+    pub fn with_overscan(mut self, overscan: usize) -> Self {
+        self.overscan = overscan;
+        self
+    }
+}
+
+impl RowHeight {
+    fn estimate(self) -> f32 {
+        match self {
+            RowHeight::Fixed(height) | RowHeight::Measured(height) => height,
+        }
+    }
+}
+
+impl ListState {
+    /// Find the rows in view of `list`, and remember them for `virtual_list_system`.
+    pub fn window(&mut self, list: &VirtualList) -> Window {
+        let estimate = list.row_height.estimate();
+        let height_of = |index: usize| match list.row_height {
+            RowHeight::Fixed(height) => height,
+            RowHeight::Measured(height) => self.measured.get(&index).copied().unwrap_or(height),
+        };
+
+        // the top of each row, and the bottom of the last row.
+        let mut tops = Vec::new();
+        if let RowHeight::Measured(_) = list.row_height {
+            tops.reserve(list.len + 1);
+            tops.push(0.0);
+            for index in 0..list.len {
+                tops.push(tops[index] + height_of(index));
+            }
+        }
+        let top_of = |index: usize| match list.row_height {
+            RowHeight::Fixed(height) => index as f32 * height,
+            RowHeight::Measured(_) => tops[index],
+        };
+        let row_at = |position: f32| match list.row_height {
+            RowHeight::Fixed(height) if height > 0.0 => (position / height) as usize,
+            RowHeight::Fixed(_) => 0,
+            RowHeight::Measured(_) => tops.partition_point(|&top| top <= position).max(1) - 1,
+        };
+
+        let height = top_of(list.len);
+        self.offset = self.offset.clamp(0.0, (height - self.viewport.y).max(0.0));
+
+        let first = row_at(self.offset).saturating_sub(list.overscan);
+        let last = (row_at(self.offset + self.viewport.y) + 1 + list.overscan).min(list.len);
+        let first = first.min(last);
+        let window = Window {
+            rows: first..last,
+            top: top_of(first),
+            height,
+            offset: self.offset,
+        };
+
+        self.height = height;
+        self.row_height = estimate;
+        self.first = first;
+        self.measured.retain(|&index, _| index < list.len);
+        window
+    }
+}
+
+/// Scroll virtual lists with the mouse wheel, and measure their size and rows after layout.
+pub(crate) fn virtual_list_system(
+    mut wheel: EventReader<MouseWheel>,
+    lists: Query<(&VirtualListNode, &Node, Option<&RelativeCursorPosition>)>,
+    rows: Query<(&VirtualListRows, &Children)>,
+    nodes: Query<&Node>,
+) {
+    let scroll: Vec<&MouseWheel> = wheel.iter().collect();
+
+    for (list, node, cursor) in lists.iter() {
+        let mut state = list.0.lock();
+        state.viewport = node.size();
+
+        if cursor.is_some_and(RelativeCursorPosition::mouse_over) {
+            for event in &scroll {
+                let lines = match event.unit {
+                    MouseScrollUnit::Line => state.row_height,
+                    MouseScrollUnit::Pixel => 1.0,
+                };
+                let max = (state.height - state.viewport.y).max(0.0);
+                state.offset = (state.offset - event.y * lines).clamp(0.0, max);
+            }
+        }
+    }
+
+    for (list, children) in rows.iter() {
+        let mut state = list.0.lock();
+        let first = state.first;
+        for (index, &child) in children.iter().enumerate() {
+            if let Ok(node) = nodes.get(child) {
+                state.measured.insert(first + index, node.size().y);
+            }
+        }
+    }
+}
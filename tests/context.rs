@@ -0,0 +1,58 @@
+use bevy::prelude::*;
+use bevy_mod_reactive_ui::*;
+
+#[derive(Event, Clone)]
+struct Ping;
+
+struct Theme(u32);
+
+/// The themes seen by the handlers of the nodes in and after the memo.
+#[derive(Resource, Default)]
+struct Seen {
+    memo: Vec<Option<u32>>,
+    after: Vec<Option<u32>>,
+}
+
+fn on_ping_memo(In(_): In<Ping>, theme: ShadowContext<Theme>, mut seen: ResMut<Seen>) {
+    seen.memo.push(theme.get().map(|theme| theme.0));
+}
+
+fn on_ping_after(In(_): In<Ping>, theme: ShadowContext<Theme>, mut seen: ResMut<Seen>) {
+    seen.after.push(theme.get().map(|theme| theme.0));
+}
+
+fn ui(mut scene: ShadowScene) {
+    scene.update(|shadow| {
+        shadow.memo("memo", 0, |shadow| {
+            shadow.provide(Theme(7), |shadow| {
+                shadow.spawn("memo", || NodeBundle::default().on_event(on_ping_memo));
+            });
+        });
+        shadow.provide(Theme(8), |shadow| {
+            shadow.spawn("after", || NodeBundle::default().on_event(on_ping_after));
+        });
+    });
+}
+
+#[test]
+fn handler_context_under_memo() {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        bevy::input::InputPlugin,
+        ShadowScenePlugin::default(),
+    ))
+    .add_ui_event::<Ping>()
+    .init_resource::<Seen>()
+    .add_systems(Update, ui.in_set(ShadowSystem::Reconcile));
+
+    app.update();
+    for _ in 0..3 {
+        app.world.send_event(Ping);
+        app.update();
+    }
+
+    let seen = app.world.resource::<Seen>();
+    assert_eq!(seen.memo, [Some(7), Some(7), Some(7)]);
+    assert_eq!(seen.after, [Some(8), Some(8), Some(8)]);
+}
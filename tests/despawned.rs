@@ -0,0 +1,166 @@
+use bevy::prelude::*;
+use bevy_mod_reactive_ui::*;
+
+#[derive(Component, Debug, PartialEq)]
+struct Row(u32);
+
+#[derive(Component)]
+struct Cell;
+
+#[derive(Resource)]
+struct Rows(Vec<u32>);
+
+/// Entities despawned by something other than the scene, before the scene is updated.
+#[derive(Resource, Default)]
+struct Despawn {
+    rows: Vec<u32>,
+    recursive: bool,
+}
+
+fn list(mut scene: ShadowScene, rows: Res<Rows>) {
+    scene.update(|shadow| {
+        shadow.spawn("list", NodeBundle::default).with(|shadow| {
+            for &row in rows.0.iter() {
+                shadow
+                    .spawn(row, || (NodeBundle::default(), Row(row)))
+                    .with(|shadow| {
+                        shadow.spawn("cell", || (NodeBundle::default(), Cell));
+                    });
+            }
+        });
+    });
+}
+
+fn despawn(mut commands: Commands, mut despawn: ResMut<Despawn>, rows: Query<(Entity, &Row)>) {
+    for (entity, row) in rows.iter() {
+        if despawn.rows.contains(&row.0) {
+            if despawn.recursive {
+                commands.entity(entity).despawn_recursive();
+            } else {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+    despawn.rows.clear();
+}
+
+fn setup() -> (World, Schedule) {
+    let mut world = World::new();
+    world.insert_resource(Rows(vec![1, 2, 3]));
+    world.init_resource::<Despawn>();
+    let mut schedule = Schedule::default();
+    schedule.add_systems((despawn, apply_deferred, list).chain());
+    schedule.run(&mut world);
+    (world, schedule)
+}
+
+fn rows(world: &mut World) -> Vec<u32> {
+    let list = world
+        .query_filtered::<&Children, Without<Row>>()
+        .iter(world)
+        .find(|children| !children.is_empty())
+        .unwrap()
+        .to_vec();
+    list.iter()
+        .filter_map(|&entity| world.get::<Row>(entity))
+        .map(|row| row.0)
+        .collect()
+}
+
+#[test]
+fn despawned_node_is_dropped() {
+    let (mut world, mut schedule) = setup();
+    *world.resource_mut::<Despawn>() = Despawn {
+        rows: vec![2],
+        recursive: true,
+    };
+    world.resource_mut::<Rows>().0 = vec![1, 3];
+    schedule.run(&mut world);
+
+    assert_eq!(rows(&mut world), [1, 3]);
+    assert_eq!(world.query::<&Cell>().iter(&world).count(), 2);
+}
+
+#[test]
+fn despawned_node_is_respawned() {
+    let (mut world, mut schedule) = setup();
+    *world.resource_mut::<Despawn>() = Despawn {
+        rows: vec![2],
+        recursive: true,
+    };
+    schedule.run(&mut world);
+
+    assert_eq!(rows(&mut world), [1, 2, 3]);
+    assert_eq!(world.query::<&Cell>().iter(&world).count(), 3);
+}
+
+#[test]
+fn orphaned_descendants_are_despawned() {
+    let (mut world, mut schedule) = setup();
+    *world.resource_mut::<Despawn>() = Despawn {
+        rows: vec![2],
+        recursive: false,
+    };
+    world.resource_mut::<Rows>().0 = vec![1, 3];
+    schedule.run(&mut world);
+
+    assert_eq!(rows(&mut world), [1, 3]);
+    assert_eq!(world.query::<&Cell>().iter(&world).count(), 2);
+
+    *world.resource_mut::<Despawn>() = Despawn {
+        rows: vec![3],
+        recursive: false,
+    };
+    schedule.run(&mut world);
+
+    assert_eq!(rows(&mut world), [1, 3]);
+    assert_eq!(world.query::<&Cell>().iter(&world).count(), 2);
+}
+
+/// Entities of which the unmount effect ran, and whether they still existed at that point.
+#[derive(Resource, Default)]
+struct Unmounted(Vec<(Entity, bool)>);
+
+fn unmount(In(entity): In<Entity>, entities: Query<()>, mut unmounted: ResMut<Unmounted>) {
+    unmounted.0.push((entity, entities.contains(entity)));
+}
+
+fn effects(mut scene: ShadowScene, rows: Res<Rows>) {
+    if rows.0.is_empty() {
+        scene.despawn();
+        return;
+    }
+    scene.update(|shadow| {
+        for &row in rows.0.iter() {
+            shadow
+                .spawn(row, || (NodeBundle::default(), Row(row)))
+                .on_unmount(unmount);
+        }
+    });
+}
+
+#[test]
+fn despawn_runs_unmount_effects() {
+    let mut world = World::new();
+    world.insert_resource(Rows(vec![1, 2]));
+    world.init_resource::<Unmounted>();
+    let mut schedule = Schedule::default();
+    schedule.add_systems(effects);
+    schedule.run(&mut world);
+    let mut rows: Vec<Entity> = world
+        .query_filtered::<Entity, With<Row>>()
+        .iter(&world)
+        .collect();
+
+    world.resource_mut::<Rows>().0.clear();
+    schedule.run(&mut world);
+
+    let mut unmounted = world.resource::<Unmounted>().0.clone();
+    unmounted.sort();
+    rows.sort();
+    assert_eq!(
+        unmounted,
+        rows.iter().map(|&row| (row, true)).collect::<Vec<_>>()
+    );
+    assert_eq!(world.query::<&Row>().iter(&world).count(), 0);
+}
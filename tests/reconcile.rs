@@ -0,0 +1,105 @@
+use bevy::prelude::*;
+use bevy_mod_reactive_ui::*;
+
+#[derive(Component, Debug, PartialEq)]
+struct Row(u32);
+
+#[derive(Component)]
+struct List;
+
+#[derive(Resource)]
+struct Rows(Vec<u32>);
+
+fn list(mut scene: ShadowScene, rows: Res<Rows>) {
+    scene.update(|shadow| {
+        shadow
+            .spawn("list", || (NodeBundle::default(), List))
+            .with(|shadow| {
+                for &row in rows.0.iter() {
+                    shadow.spawn(row, || (NodeBundle::default(), Row(row)));
+                }
+            });
+    });
+}
+
+fn setup(rows: Vec<u32>) -> (World, Schedule) {
+    let mut world = World::new();
+    world.insert_resource(Rows(rows));
+    let mut schedule = Schedule::default();
+    schedule.add_systems(list);
+    schedule.run(&mut world);
+    (world, schedule)
+}
+
+/// The rows in the order of the `Children` of the list, with their entities.
+fn children(world: &mut World) -> Vec<(u32, Entity)> {
+    let children = world
+        .query_filtered::<&Children, With<List>>()
+        .single(world)
+        .to_vec();
+    children
+        .into_iter()
+        .map(|entity| (world.get::<Row>(entity).unwrap().0, entity))
+        .collect()
+}
+
+/// Render `before`, then `after`, and check that the rows end up in the order of `after` with
+/// the entities they had before.
+fn reconcile(before: Vec<u32>, after: Vec<u32>) {
+    let (mut world, mut schedule) = setup(before.clone());
+    let entities = children(&mut world);
+    assert_eq!(
+        entities.iter().map(|&(row, _)| row).collect::<Vec<_>>(),
+        before
+    );
+
+    world.resource_mut::<Rows>().0 = after.clone();
+    schedule.run(&mut world);
+    let reconciled = children(&mut world);
+    assert_eq!(
+        reconciled.iter().map(|&(row, _)| row).collect::<Vec<_>>(),
+        after
+    );
+    for (row, entity) in reconciled {
+        if let Some(&(_, previous)) = entities.iter().find(|&&(r, _)| r == row) {
+            assert_eq!(entity, previous, "row {row} was respawned");
+        }
+    }
+    assert_eq!(world.query::<&Row>().iter(&world).count(), after.len());
+}
+
+#[test]
+fn reverse() {
+    reconcile((0..10).collect(), (0..10).rev().collect());
+}
+
+#[test]
+fn move_first_to_last() {
+    reconcile((0..10).collect(), (1..10).chain([0]).collect());
+}
+
+#[test]
+fn move_last_to_first() {
+    reconcile((0..10).collect(), [9].into_iter().chain(0..9).collect());
+}
+
+#[test]
+fn insert_in_middle() {
+    reconcile(
+        (0..10).collect(),
+        (0..5).chain([100, 101]).chain(5..10).collect(),
+    );
+}
+
+#[test]
+fn remove_in_middle() {
+    reconcile(
+        (0..10).collect(),
+        (0..10).filter(|&row| row != 4 && row != 5).collect(),
+    );
+}
+
+#[test]
+fn shuffle() {
+    reconcile((0..10).collect(), vec![3, 11, 7, 0, 9, 12, 1, 5, 2]);
+}
@@ -0,0 +1,120 @@
+use bevy::{ecs::component::Tick, prelude::*};
+use bevy_mod_reactive_ui::*;
+
+#[derive(Component, Reflect, Clone, Debug, Default, PartialEq)]
+#[reflect(Component)]
+struct Size(f32);
+
+#[derive(Component, Reflect, Clone, Debug, Default, PartialEq)]
+#[reflect(Component)]
+struct Label(String);
+
+/// Not registered for reflection, so it can't be compared.
+#[derive(Component, Debug, PartialEq)]
+struct Opaque(u32);
+
+#[derive(Resource)]
+struct Input {
+    size: f32,
+    opaque: u32,
+    switched: bool,
+}
+
+fn node(mut scene: ShadowScene, input: Res<Input>) {
+    scene.update(|shadow| {
+        if input.switched {
+            shadow.spawn_diff("node", || (Size(input.size), Label("switched".into())));
+        } else {
+            shadow.spawn_diff("node", || (Size(input.size), Opaque(input.opaque)));
+        }
+    });
+}
+
+fn setup() -> (World, Schedule) {
+    let mut world = World::new();
+    let registry = AppTypeRegistry::default();
+    registry.write().register::<Size>();
+    registry.write().register::<Label>();
+    world.insert_resource(registry);
+    world.insert_resource(Input {
+        size: 1.0,
+        opaque: 1,
+        switched: false,
+    });
+    let mut schedule = Schedule::default();
+    schedule.add_systems(node);
+    (world, schedule)
+}
+
+fn run(world: &mut World, schedule: &mut Schedule) -> Entity {
+    world.increment_change_tick();
+    schedule.run(world);
+    world.query_filtered::<Entity, With<Size>>().single(world)
+}
+
+fn changed<T: Component>(world: &World, entity: Entity) -> Tick {
+    world
+        .entity(entity)
+        .get_change_ticks::<T>()
+        .unwrap()
+        .last_changed_tick()
+}
+
+#[test]
+fn first_insert() {
+    let (mut world, mut schedule) = setup();
+    let entity = run(&mut world, &mut schedule);
+
+    let entity = world.entity(entity);
+    assert_eq!(entity.get::<Size>(), Some(&Size(1.0)));
+    assert_eq!(entity.get::<Opaque>(), Some(&Opaque(1)));
+}
+
+#[test]
+fn unchanged_frame() {
+    let (mut world, mut schedule) = setup();
+    let entity = run(&mut world, &mut schedule);
+    let size = changed::<Size>(&world, entity);
+    let opaque = changed::<Opaque>(&world, entity);
+
+    assert_eq!(run(&mut world, &mut schedule), entity);
+    assert_eq!(changed::<Size>(&world, entity), size);
+    // components that can't be compared are written every time.
+    assert_ne!(changed::<Opaque>(&world, entity), opaque);
+}
+
+#[test]
+fn changed_field() {
+    let (mut world, mut schedule) = setup();
+    let entity = run(&mut world, &mut schedule);
+    let size = changed::<Size>(&world, entity);
+
+    world.resource_mut::<Input>().size = 2.0;
+    world.resource_mut::<Input>().opaque = 2;
+    assert_eq!(run(&mut world, &mut schedule), entity);
+    assert_ne!(changed::<Size>(&world, entity), size);
+    assert_eq!(world.get::<Size>(entity), Some(&Size(2.0)));
+    assert_eq!(world.get::<Opaque>(entity), Some(&Opaque(2)));
+}
+
+#[test]
+fn bundle_type_switch() {
+    let (mut world, mut schedule) = setup();
+    let entity = run(&mut world, &mut schedule);
+
+    world.resource_mut::<Input>().size = 2.0;
+    world.resource_mut::<Input>().switched = true;
+    assert_eq!(run(&mut world, &mut schedule), entity);
+    assert_eq!(world.get::<Size>(entity), Some(&Size(2.0)));
+    assert_eq!(world.get::<Label>(entity), Some(&Label("switched".into())));
+    assert_eq!(world.get::<Opaque>(entity), None);
+
+    // a bundle of another type is inserted as a whole.
+    let size = changed::<Size>(&world, entity);
+    world.resource_mut::<Input>().switched = false;
+    assert_eq!(run(&mut world, &mut schedule), entity);
+    assert_eq!(world.get::<Size>(entity), Some(&Size(2.0)));
+    assert_eq!(world.get::<Opaque>(entity), Some(&Opaque(1)));
+    assert_eq!(world.get::<Label>(entity), None);
+    assert_ne!(changed::<Size>(&world, entity), size);
+}
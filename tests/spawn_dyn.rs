@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+use bevy_mod_reactive_ui::*;
+
+#[derive(Component, Debug, PartialEq)]
+struct Row(u32);
+
+#[derive(Component)]
+struct Kill;
+
+#[derive(Resource)]
+struct Input {
+    row: u32,
+    kill: bool,
+}
+
+fn row(mut scene: ShadowScene, input: Res<Input>) {
+    let update = input.is_changed();
+    scene.update(|shadow| {
+        if input.kill {
+            shadow.spawn_dyn("row", update, || {
+                (NodeBundle::default(), Row(input.row), Kill)
+            });
+        } else {
+            shadow.spawn_dyn("row", update, || (NodeBundle::default(), Row(input.row)));
+        }
+    });
+}
+
+#[test]
+fn bundle_type_switch() {
+    let mut world = World::new();
+    world.insert_resource(Input { row: 1, kill: true });
+    let mut schedule = Schedule::default();
+    schedule.add_systems(row);
+
+    schedule.run(&mut world);
+    let entity = world.query_filtered::<Entity, With<Row>>().single(&world);
+    assert!(world.entity(entity).contains::<Kill>());
+
+    *world.resource_mut::<Input>() = Input {
+        row: 2,
+        kill: false,
+    };
+    schedule.run(&mut world);
+    assert_eq!(world.query::<&Row>().iter(&world).count(), 1);
+    assert_eq!(world.get::<Row>(entity), Some(&Row(2)));
+    assert!(world.entity(entity).contains::<Style>());
+    assert!(!world.entity(entity).contains::<Kill>());
+}
+
+#[derive(Resource)]
+struct Enabled(bool);
+
+fn on_click() {}
+
+fn button(mut scene: ShadowScene, enabled: Res<Enabled>) {
+    scene.update(|shadow| {
+        if enabled.0 {
+            shadow.spawn_dyn("button", true, || {
+                ButtonBundle::default().on_click(on_click)
+            });
+        } else {
+            shadow.spawn_dyn("button", true, ButtonBundle::default);
+        }
+    });
+}
+
+#[test]
+fn button_becomes_disabled() {
+    use bevy::{
+        ui::{ui_layout_system, UiSurface},
+        window::{PrimaryWindow, WindowResized, WindowScaleFactorChanged},
+    };
+
+    let mut world = World::new();
+    world.spawn((Window::default(), PrimaryWindow));
+    world.init_resource::<UiScale>();
+    world.init_resource::<UiSurface>();
+    world.init_resource::<Events<WindowResized>>();
+    world.init_resource::<Events<WindowScaleFactorChanged>>();
+    world.insert_resource(Enabled(true));
+    let mut schedule = Schedule::default();
+    schedule.add_systems((button, apply_deferred, ui_layout_system).chain());
+
+    schedule.run(&mut world);
+    let entity = world
+        .query_filtered::<Entity, With<Button>>()
+        .single(&world);
+    assert!(world
+        .entity(entity)
+        .contains::<InteractionHandler<OnClick>>());
+
+    // the layout would lose the node if the components both bundles have were removed.
+    world.resource_mut::<Enabled>().0 = false;
+    schedule.run(&mut world);
+    schedule.run(&mut world);
+    assert!(!world
+        .entity(entity)
+        .contains::<InteractionHandler<OnClick>>());
+    assert!(world.entity(entity).contains::<Node>());
+    assert_eq!(world.removed::<Node>().count(), 0);
+}